@@ -1,52 +1,129 @@
 mod blockchain;
+mod mining;
 
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
 use std::str::FromStr;
-pub use blockchain::{Block, GamePad};
-use blockchain::{BlockChain, Ticket};
+pub use blockchain::{Block, GamePad, ConnectionState, Discovery, GcStats, RejectReason, Storage, SubmitOutcome};
+#[cfg(feature = "test-utils")]
+pub use blockchain::{LocalDht, LocalNet};
+use blockchain::{BlockChain, Network, Ticket};
+pub use mining::{MiningSession, DEFAULT_MAX_FORK_DEPTH};
+use chrono::{DateTime, Utc};
+use futures_lite::Stream;
 use hex::ToHex;
+use iroh::EndpointId;
 use iroh_blobs::Hash;
 use iroh_gossip::TopicId;
-use sm64_binds::{RngConfig, SM64GameGenerator};
+use sm64_binds::SM64GameGenerator;
 use crate::CHAIN_CFG;
+use crate::trace::{Trace, TraceFormat};
+use crate::verifier::GameVerifier;
 
-use anyhow::{Result, Error};
+use crate::error::{config, validation, Result};
 
 #[derive(Debug)]
-pub struct BlockChainClient {
-    bc: BlockChain,
-    mining_block: Option<Block>,
+pub struct BlockChainClient<G: GameVerifier = SM64GameGenerator> {
+    bc: BlockChain<G>,
     miner_name: String,
-    topic_id: TopicId
+    max_fork_depth: u128,
+    topic_id: TopicId,
+    discovery: Option<Discovery>,
 }
 
 impl BlockChainClient {
-    pub async fn new(rom_bytes: Vec<u8>, miner_name: String, ticket_opt: Option<String>) -> Result<Self> {
+    // storage picks a directory for the store, or keeps everything in memory
+    // persist_key keeps the same node identity across restarts (needs on-disk storage)
+    // discovery finds peers on the topic without needing a ticket
+    pub async fn new(
+        rom_bytes: Vec<u8>, miner_name: String, ticket_opt: Option<String>,
+        storage: Storage, persist_key: bool, discovery: Option<Discovery>
+    ) -> Result<Self> {
+        let game_gen = SM64GameGenerator::new(rom_bytes)
+            .map_err(|e| config(format!("Couldn't load the ROM: {}", e)))?;
+
+        Self::with_verifier(game_gen, miner_name, ticket_opt, storage, persist_key, discovery).await
+    }
+}
+
+impl<G: GameVerifier> BlockChainClient<G> {
+    // Like new, but blocks are replayed with the given verifier instead of the real game
+    pub async fn with_verifier(
+        verifier: G, miner_name: String, ticket_opt: Option<String>,
+        storage: Storage, persist_key: bool, discovery: Option<Discovery>
+    ) -> Result<Self> {
+        let net = Network::new(storage, persist_key).await?;
+        Self::open(verifier, miner_name, ticket_opt, net, discovery).await
+    }
+
+    // Like with_verifier, but the node runs on an in-process network instead of the internet
+    #[cfg(feature = "test-utils")]
+    pub async fn on_local_net(
+        local_net: LocalNet, verifier: G, miner_name: String, ticket_opt: Option<String>,
+        storage: Storage, discovery: Option<Discovery>
+    ) -> Result<Self> {
+        let net = Network::on_local_net(storage, local_net).await?;
+        Self::open(verifier, miner_name, ticket_opt, net, discovery).await
+    }
+
+    async fn open(
+        verifier: G, miner_name: String, ticket_opt: Option<String>, net: Network, discovery: Option<Discovery>
+    ) -> Result<Self> {
         if miner_name.len() > CHAIN_CFG.max_name_length {
-            return Err(Error::msg("Miner name is too long"));
+            return Err(config("Miner name is too long").into());
         }
 
-        let game_gen = SM64GameGenerator::new(rom_bytes)?;
-
         let ticket = match ticket_opt {
             Some(ticket_str) => {
-                Ticket::deserialize(&ticket_str)?
+                Some(Ticket::deserialize(&ticket_str)?)
             },
-            None => {
-                Ticket::new_random()
-            }
+            None => None
         };
 
-        let topic_id = ticket.topic_id;
-        let bc = BlockChain::new(game_gen, ticket).await?;
+        let bc = BlockChain::new(verifier, net, ticket, discovery.clone()).await?;
+        let topic_id = bc.chain_id();
 
         Ok(Self {
             bc,
             topic_id,
-            mining_block: None,
             miner_name,
+            max_fork_depth: DEFAULT_MAX_FORK_DEPTH,
+            discovery,
         })
     }
 
+    // A client for another chain (e.g. a testnet) sharing this client's endpoint and store
+    pub async fn follow(&self, ticket_str: String) -> Result<Self> {
+        let ticket = Ticket::deserialize(&ticket_str)?;
+        let bc = self.bc.follow(ticket, self.discovery.clone()).await?;
+        let topic_id = bc.chain_id();
+
+        Ok(Self {
+            bc,
+            topic_id,
+            miner_name: self.miner_name.clone(),
+            max_fork_depth: self.max_fork_depth,
+            discovery: self.discovery.clone(),
+        })
+    }
+
+    // Who this node is on the network, e.g. for LocalNet::isolate
+    pub fn endpoint_id(&self) -> EndpointId {
+        self.bc.endpoint_id()
+    }
+
+    // The gossip topic of this client's chain, for tests that join it by hand
+    #[cfg(feature = "test-utils")]
+    pub fn topic_id(&self) -> TopicId {
+        self.topic_id
+    }
+
+    // The peers we're currently connected to
+    pub async fn live_peers(&self) -> Vec<EndpointId> {
+        self.bc.live_peers().await
+    }
+
     pub fn get_ticket(&self) -> String {
         let topic_id = self.topic_id;
         let bootstrap = [self.bc.endpoint_id()].into_iter().collect();
@@ -54,41 +131,65 @@ impl BlockChainClient {
         ticket.serialize()
     }
 
-    pub async fn start_mine(&mut self) -> Result<(u32, RngConfig)> {
-        self.bc.start_mine().await;
+    // Like get_ticket, but also lists up to max_peers of our current neighbours as bootstrap nodes
+    pub async fn get_ticket_with_peers(&self, max_peers: usize) -> String {
+        let topic_id = self.topic_id;
+        let mut bootstrap: BTreeSet<EndpointId> = [self.bc.endpoint_id()].into_iter().collect();
+        bootstrap.extend(self.bc.live_peers().await.into_iter().take(max_peers));
+        let ticket = Ticket {topic_id, bootstrap};
+        ticket.serialize()
+    }
 
-        let head = self.bc.get_head_public().await?;
-        let block = Block::new(head, self.miner_name.clone())?;
+    // Starts mining on top of the current head. Play the game with the session's seed and rng config,
+    // then submit the inputs on the session. Several sessions can run at once
+    pub async fn start_mine(&self) -> Result<MiningSession<G>> {
+        MiningSession::new(self.bc.clone(), self.miner_name.clone(), self.max_fork_depth).await
+    }
 
-        let seed = block.calc_seed();
-        let cfg = block.calc_rng_config();
+    // How far below the head a session's parent can fall before its block is rejected instead of
+    // being kept as a fork block. 0 only accepts blocks that become the head
+    pub fn set_max_fork_depth(&mut self, depth: u128) {
+        self.max_fork_depth = depth;
+    }
 
-        self.mining_block = Some(block);
+    // Writes the canonical chain, or just the heights in range, as an archive. Returns the number of blocks
+    pub async fn export_chain(&self, range: Option<RangeInclusive<u128>>, writer: impl Write) -> Result<u64> {
+        Ok(self.bc.export_archive(range, writer).await?)
+    }
 
-        Ok((seed, cfg))
+    // Replays every block in an archive made by export_chain and adopts it if it beats our head
+    pub async fn import_chain(&self, reader: impl Read) -> Result<u64> {
+        Ok(self.bc.import_archive(reader).await?)
     }
 
-    pub async fn submit_mine(&mut self, seed: u32, solution: Vec<GamePad>) -> Result<()> {
-        match self.mining_block.clone() {
-            Some(mut block) => {
-                if block.calc_seed() != seed {
-                    return Err(Error::msg("The provided seed does not match start_mine()"));
-                }
+    // Brings the gameplay dataset in `dir` up to our head, one Arrow IPC file per blocks_per_file new blocks.
+    // traces adds per-frame Mario state columns. Returns the number of new episodes
+    #[cfg(feature = "dataset")]
+    pub async fn export_dataset(&self, dir: &std::path::Path, traces: bool, blocks_per_file: u64) -> Result<u64> {
+        Ok(self.bc.export_dataset(dir, traces, blocks_per_file).await?)
+    }
 
-                block.seal(solution)?;
-                match self.bc.submit_mine(block).await {
-                    Ok(_) => {
-                        self.mining_block = None;
-                        Ok(())
-                    }
-                    Err(e) => Err(e)
-                }
-            }
-            None => {
-                Err(Error::msg("Block does not exist or has already been mined. Use start_mine()"))
-            }
-        }
+    // Frees blobs no chain on this node needs any more, keeping side-chain blocks within fork_window of a head
+    pub async fn collect_garbage(&self, fork_window: u128) -> Result<GcStats> {
+        Ok(self.bc.collect_garbage(fork_window).await?)
+    }
+
+    // Replays a block frame by frame, for seeing what happened in a run (e.g. one that failed validation).
+    // Starting the game and every frame run the emulator on this thread, so async callers should go through
+    // spawn_blocking or block_in_place
+    pub fn trace_block(&self, block: &Block) -> Result<Trace<G::Game>> {
+        Trace::new(self.bc.verifier(), block)
     }
+
+    // Writes a block's replay trace, returns the number of frames. Blocks for the whole replay, like trace_block
+    pub fn export_trace(&self, block: &Block, format: TraceFormat, writer: impl Write) -> Result<u64> {
+        self.trace_block(block)?.write(format, writer)
+    }
+
+    pub async fn connection_state(&self) -> ConnectionState {
+        self.bc.connection_state().await
+    }
+
     pub async fn has_new_block(&self) -> bool {
         self.bc.has_new_block().await
     }
@@ -99,9 +200,60 @@ impl BlockChainClient {
     }
 
     pub async fn get_block_from_str(&self, hash_str: String) -> Result<Block> {
-        let hash = Hash::from_str(&hash_str)?;
+        let hash = Hash::from_str(&hash_str).map_err(|_| validation("Not a block hash"))?;
         let block = self.bc.get_local_block_public(hash).await?;
         Ok(block)
     }
 
+    // Downloads the block from the given peers as if one of them had announced it
+    #[cfg(feature = "test-utils")]
+    pub async fn fetch_block(&self, hash_str: String, peers: Vec<EndpointId>) -> Result<Block> {
+        let hash = Hash::from_str(&hash_str).map_err(|_| validation("Not a block hash"))?;
+        Ok(self.bc.get_foreign_block_public(hash, peers).await?)
+    }
+
+    // Whether any of the blob is in our store, e.g. to check a rejected download was cleaned up
+    #[cfg(feature = "test-utils")]
+    pub async fn holds_blob(&self, hash_str: String) -> Result<bool> {
+        let hash = Hash::from_str(&hash_str).map_err(|_| validation("Not a block hash"))?;
+        Ok(self.bc.holds_blob(hash).await?)
+    }
+
+    // The block at height on our canonical chain, None if the chain isn't that long yet
+    pub async fn get_block_by_height(&self, height: u128) -> Result<Option<Block>> {
+        Ok(self.bc.get_block_at_height_public(height).await?)
+    }
+
+    // The canonical blocks in range, stopping early at the head
+    pub async fn get_blocks(&self, range: RangeInclusive<u128>) -> Result<Vec<Block>> {
+        Ok(self.bc.get_blocks_public(range).await?)
+    }
+
+    // Every block on our canonical chain mined under this name, lowest first
+    pub async fn get_blocks_by_miner(&self, miner_name: &str) -> Result<Vec<Block>> {
+        Ok(self.bc.blocks_by_miner_public(miner_name).await?)
+    }
+
+    // Every block on our canonical chain with a timestamp from `from` up to (not including) `to`, oldest first
+    pub async fn get_blocks_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Block>> {
+        Ok(self.bc.blocks_between_public(from, to).await?)
+    }
+
+    // Walks the canonical chain upwards from height `from`, one block at a time, until it reaches the head.
+    // Each height is looked up as it's reached, so a reorg part way through shows up in the later blocks
+    pub fn blocks_from(&self, from: u128) -> impl Stream<Item = Result<Block>> + 'static {
+        let bc = self.bc.clone();
+        futures_lite::stream::unfold(Some(from), move |height| {
+            let bc = bc.clone();
+            async move {
+                let height = height?;
+                match bc.get_block_at_height_public(height).await {
+                    Ok(Some(block)) => Some((Ok(block), height.checked_add(1))),
+                    Ok(None) => None,
+                    Err(e) => Some((Err(e.into()), None)),
+                }
+            }
+        })
+    }
+
 }
\ No newline at end of file
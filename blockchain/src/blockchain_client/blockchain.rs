@@ -1,21 +1,15 @@
-use std::u128;
 use bytes::Bytes;
-use futures_lite::StreamExt;
-use iroh_blobs::api::Store;
-#[cfg(not(feature = "fs"))]
-use iroh_blobs::store::mem::MemStore;
-use n0_future::task;
-use anyhow::{Error, Result};
-use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc, Local};
-
-// use distributed_topic_tracker::{AutoDiscoveryGossip, RecordPublisher, TopicId, GossipReceiver, GossipSender};
-// use mainline::SigningKey;
-
-use iroh_blobs::{api::{ downloader::{Downloader, Shuffled}, tags::Tags }, BlobsProtocol, Hash };
-use iroh::{Endpoint, EndpointId };
+use futures_lite::{FutureExt, StreamExt};
+use n0_future::{task, time::{self, Duration}};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+
+use iroh_blobs::{api::{ downloader::{DownloadProgressItem, Downloader, Shuffled}, tags::Tags }, BlobsProtocol, Hash };
+#[cfg(feature = "test-utils")]
+use iroh_blobs::api::blobs::BlobStatus;
+use iroh::EndpointId;
 use iroh_gossip::{
-    api::{Event, GossipReceiver, GossipSender}, net::Gossip,
+    api::{Event, GossipReceiver, GossipSender}, net::Gossip, TopicId,
     proto::DeliveryScope::{Neighbors, Swarm}
 };
 
@@ -23,67 +17,223 @@ use iroh::protocol::Router;
 use tracing::info;
 // use iroh_docs::{protocol::Docs};
 // use std::sync::{Arc, Mutex};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::RangeInclusive;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{mpsc, watch, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use rand::seq::IndexedRandom;
 
-pub use sm64_binds::{GamePad, SM64GameGenerator};
+pub use sm64_binds::GamePad;
+use crate::CHAIN_CFG;
+use crate::verifier::{Game, GameVerifier};
+use crate::error::{config, game, invalid_block, is_invalid_block, network, validation};
 
+mod archive;
 mod block;
+#[cfg(feature = "dataset")]
+mod dataset;
+mod discovery;
+mod gc;
+mod index;
+mod journal;
+#[cfg(feature = "test-utils")]
+mod local_net;
+mod message;
+mod network;
+mod peers;
 mod ticket;
 
 pub use block::{BlockHead, Block};
+use index::index_entries;
+use journal::Journal;
+use message::{BlockMessage, Capabilities, Node, ReplayGuard, open_message, PROTOCOL_VERSION};
+use peers::{Offence, PeerBook, PeerScores, RateLimiter};
 pub use ticket::Ticket;
+pub use discovery::Discovery;
+#[cfg(feature = "test-utils")]
+pub use discovery::LocalDht;
+#[cfg(feature = "test-utils")]
+pub use local_net::LocalNet;
+pub use network::{Network, Storage};
+pub use gc::GcStats;
+
+// Gossip messages waiting for the db_lock, anything beyond this is dropped
+const WORK_QUEUE_SIZE: usize = 32;
+// How often we re-announce ourselves and look for new peers in the DHT
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Waits between gossip re-subscriptions, doubling on every failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// How often we compare heads with a random neighbour, and how many of those rounds between full re-announcements
+const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(30);
+const REANNOUNCE_EVERY: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    // Subscribed to the topic but no neighbours yet
+    Joining,
+    Connected,
+    // The gossip subscription died, waiting to re-subscribe
+    Disconnected,
+}
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Joining => "joining",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+        }
+    }
+}
+
+// What happened to a block we mined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitOutcome {
+    // It's our new head and has been announced
+    AcceptedAsHead,
+    // It's valid but below our head, so it's only stored. It joins the chain if a longer chain built on it reaches us
+    AcceptedAsSideChain,
+    Rejected(RejectReason),
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    // Another block replaced the head it was mined on, and the parent is too deep to fork from
+    StaleParent,
+    // The inputs don't win the game with this block's seed
+    ReplayFailed,
+    // The solution is longer than max_solution_time
+    TooLong,
+}
+
+impl SubmitOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubmitOutcome::AcceptedAsHead => "head",
+            SubmitOutcome::AcceptedAsSideChain => "side_chain",
+            SubmitOutcome::Rejected(RejectReason::StaleParent) => "stale_parent",
+            SubmitOutcome::Rejected(RejectReason::ReplayFailed) => "replay_failed",
+            SubmitOutcome::Rejected(RejectReason::TooLong) => "too_long",
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        !matches!(self, SubmitOutcome::Rejected(_))
+    }
+}
 
 #[derive(Debug)]
-pub struct BlockChain {
-    router: Router, downloader: Downloader, blobs: BlobsProtocol, tags: Tags, sender: GossipSender, game_gen: SM64GameGenerator,
-    db_lock: Arc<Mutex<()>>, new_block_signal: Arc<Mutex<bool>>,
+pub struct BlockChain<G: GameVerifier> {
+    router: Router, downloader: Downloader, blobs: BlobsProtocol, tags: Tags, gossip: Gossip, sender: Arc<Mutex<GossipSender>>,
+    game_gen: G, bootstrap: Vec<EndpointId>, connection: Arc<Mutex<ConnectionState>>,
+    db_lock: Arc<Mutex<()>>, gc_lock: Arc<RwLock<()>>, sweep: Arc<AtomicBool>, new_block_signal: Arc<Mutex<bool>>,
+    head_watch: Arc<watch::Sender<BlockHead>>, peers: Arc<std::sync::Mutex<PeerScores>>, bans: Arc<watch::Sender<()>>,
+    peer_caps: Arc<Mutex<HashMap<EndpointId, Capabilities>>>, peer_book: Arc<Mutex<PeerBook>>, chain_id: TopicId,
+    #[cfg(feature = "test-utils")]
+    local_net: Option<LocalNet>,
 }
-impl Clone for BlockChain {
+impl<G: GameVerifier> Clone for BlockChain<G> {
     fn clone(&self) -> Self {
         BlockChain {
             router: self.router.clone(),
             downloader: self.downloader.clone(), 
             blobs: self.blobs.clone(),
             tags: self.tags.clone(),
-            sender: self.sender.clone(),
+            gossip: self.gossip.clone(),
+            sender: Arc::clone(&self.sender),
             game_gen: self.game_gen.clone(),
+            bootstrap: self.bootstrap.clone(),
+            connection: Arc::clone(&self.connection),
             db_lock: Arc::clone(&self.db_lock), // We need to make sure it uses this function not just .clone()
+            gc_lock: Arc::clone(&self.gc_lock),
+            sweep: Arc::clone(&self.sweep),
             new_block_signal: Arc::clone(&self.new_block_signal),
+            head_watch: Arc::clone(&self.head_watch),
+            peers: Arc::clone(&self.peers),
+            bans: Arc::clone(&self.bans),
+            peer_caps: Arc::clone(&self.peer_caps),
+            peer_book: Arc::clone(&self.peer_book),
+            chain_id: self.chain_id,
+            #[cfg(feature = "test-utils")]
+            local_net: self.local_net.clone(),
         }
     }
 }
 
-impl BlockChain {
-    pub async fn new(game_gen: SM64GameGenerator, ticket: Ticket) -> Result<Self> {
-        let endpoint = Endpoint::builder().bind().await?;
-
-        let store = load_store().await;
-
-        let blobs = BlobsProtocol::new(&store, None);
-        let gossip = Gossip::builder().spawn(endpoint.clone());
-        let tags = blobs.tags().clone();
-        let downloader = store.downloader(&endpoint);
-
-        // Setup router
-        let router = Router::builder(endpoint.clone())
-            .accept(iroh_blobs::ALPN, blobs.clone())
-            .accept(iroh_gossip::ALPN, gossip.clone())
-            .spawn();
+impl<G: GameVerifier> BlockChain<G> {
+    // Without a ticket we rejoin the last topic we were on, then try the well-known topic if we have discovery,
+    // otherwise we start a new chain
+    pub async fn new(
+        game_gen: G, net: Network, ticket_opt: Option<Ticket>, discovery: Option<Discovery>
+    ) -> Result<Self> {
+        let ticket = match (ticket_opt, net.last_topic().await?) {
+            (Some(ticket), _) => ticket,
+            (None, Some(topic_id)) => {
+                info!("Rejoining topic {}", topic_id);
+                Ticket::new(topic_id)
+            },
+            (None, None) if discovery.is_some() => Ticket::well_known(),
+            (None, None) => Ticket::new_random(),
+        };
+        net.set_last_topic(ticket.topic_id).await?;
+        Self::open(game_gen, net, ticket, discovery).await
+    }
 
+    // Follows another chain on the same endpoint and store as this one
+    pub async fn follow(&self, ticket: Ticket, discovery: Option<Discovery>) -> Result<Self> {
+        if ticket.topic_id == self.chain_id {
+            return Err(config("Already following this chain"));
+        }
+        Self::open(self.game_gen.clone(), self.network(), ticket, discovery).await
+    }
 
+    async fn open(game_gen: G, net: Network, ticket: Ticket, discovery: Option<Discovery>) -> Result<Self> {
         let db_lock = Arc::new(Mutex::new(()));
         let new_block_signal = Arc::new(Mutex::new(false));
+        let head_watch = Arc::new(watch::channel(BlockHead::default()).0);
+        let peer_caps = Arc::new(Mutex::new(HashMap::new()));
 
-        let topic_id = ticket.topic_id;
-        let bootstrap = ticket.bootstrap.iter().cloned().collect();
-        let (sender, receiver) = gossip.subscribe(topic_id, bootstrap).await?.split();
+        let peer_book = load_peer_book(&net, ticket.topic_id).await?
+            .unwrap_or_else(|| PeerBook::new(ticket.topic_id));
+        #[cfg(feature = "test-utils")]
+        let local_net = net.local_net.clone();
+        let Network {router, downloader, blobs, tags, gossip, peers, bans, gc_lock, sweep, ..} = net;
 
-        let bc = BlockChain {router, downloader, blobs, tags, sender, game_gen, db_lock, new_block_signal};
+        let topic_id = ticket.topic_id;
+        let bootstrap: Vec<EndpointId> = ticket.bootstrap.iter().cloned().collect();
+        let mut first_peers: BTreeSet<EndpointId> = bootstrap.iter().cloned().collect();
+        first_peers.extend(peer_book.by_recency());
+        // Peers are joined once the chain is up, so banned ones can be filtered out
+        let (sender, receiver) = gossip.subscribe(topic_id, Vec::new()).await
+            .map_err(network("Couldn't subscribe to the topic"))?.split();
+
+        let chain_id = topic_id;
+        let sender = Arc::new(Mutex::new(sender));
+        let peer_book = Arc::new(Mutex::new(peer_book));
+        let connection = Arc::new(Mutex::new(ConnectionState::Joining));
+        let bc = BlockChain {
+            router, downloader, blobs, tags, gossip, sender, game_gen, bootstrap, connection,
+            db_lock, gc_lock, sweep, new_block_signal, head_watch, peers, bans, peer_caps, peer_book, chain_id,
+            #[cfg(feature = "test-utils")]
+            local_net,
+        };
+        bc.migrate_legacy_tags().await?;
+        bc.recover().await?;
+        bc.head_watch.send_replace(bc.get_head_public().await?);
+        let first_peers = bc.usable_peers(first_peers.into_iter().collect()).await;
+        if !first_peers.is_empty() {
+            bc.sender.lock().await.join_peers(first_peers).await
+                .map_err(network("Couldn't subscribe to the topic"))?;
+        }
         let bc2 = bc.clone();
 
-        task::spawn(subscribe_loop(bc2, receiver));
+        task::spawn(gossip_supervisor(bc2, receiver));
+        task::spawn(anti_entropy_loop(bc.clone()));
+        if let Some(discovery) = discovery {
+            task::spawn(discovery_loop(bc.clone(), discovery));
+        }
 
         Ok(bc)
     }
@@ -92,6 +242,74 @@ impl BlockChain {
         self.router.endpoint().id()
     }
 
+    pub fn chain_id(&self) -> TopicId {
+        self.chain_id
+    }
+
+    pub fn verifier(&self) -> &G {
+        &self.game_gen
+    }
+
+    // For following another chain on the same endpoint and store
+    pub fn network(&self) -> Network {
+        Network {
+            router: self.router.clone(),
+            downloader: self.downloader.clone(),
+            blobs: self.blobs.clone(),
+            tags: self.tags.clone(),
+            gossip: self.gossip.clone(),
+            peers: Arc::clone(&self.peers),
+            bans: Arc::clone(&self.bans),
+            gc_lock: Arc::clone(&self.gc_lock),
+            sweep: Arc::clone(&self.sweep),
+            #[cfg(feature = "test-utils")]
+            local_net: self.local_net.clone(),
+        }
+    }
+
+    // Every chain operation holds this. The db_lock must come first, garbage collection only ever takes the gc_lock
+    async fn lock_db(&self) -> (MutexGuard<'_, ()>, RwLockReadGuard<'_, ()>) {
+        let db_guard = self.db_lock.lock().await;
+        let gc_guard = self.gc_lock.read().await;
+        (db_guard, gc_guard)
+    }
+
+    fn tag(&self, name: &str) -> String {
+        chain_tag(self.chain_id, name)
+    }
+
+    pub async fn connection_state(&self) -> ConnectionState {
+        *self.connection.lock().await
+    }
+
+    async fn set_connection_state(&self, state: ConnectionState) {
+        info!("SUBLOOP: {}", state.as_str());
+        *self.connection.lock().await = state;
+    }
+
+    // Replaces a dead gossip subscription with a fresh one, bootstrapping from everyone we know
+    async fn resubscribe(&self) -> Result<GossipReceiver> {
+        let mut peers: BTreeSet<EndpointId> = self.bootstrap.iter().cloned().collect();
+        peers.extend(self.peer_book.lock().await.by_recency());
+        let peers = self.usable_peers(peers.into_iter().collect()).await;
+
+        let (sender, receiver) = self.gossip.subscribe(self.chain_id, peers).await
+            .map_err(network("Couldn't subscribe to the topic"))?.split();
+        *self.sender.lock().await = sender;
+        Ok(receiver)
+    }
+
+    async fn broadcast(&self, encoded: Bytes) -> Result<()> {
+        let sender = self.sender.lock().await.clone();
+        sender.broadcast_neighbors(encoded).await?;
+        Ok(())
+    }
+
+    // Peers we are currently connected to, for handing out in tickets
+    pub async fn live_peers(&self) -> Vec<EndpointId> {
+        self.peer_book.lock().await.live_peers()
+    }
+
     async fn print_state(&self) -> Result<()> {
         let head = self.get_head().await?;
         if !head.no_blocks() {
@@ -105,21 +323,20 @@ impl BlockChain {
     }
 
     async fn get_head(&self) -> Result<BlockHead> {
-        let ot = self.tags.get(String::from("head")).await?;
+        let ot = self.tags.get(self.tag("head")).await?;
         match ot {
             Some(t) => {
                 let bytes = self.blobs.get_bytes(t.hash).await?;
-                return Ok(BlockHead::decode(&bytes)?);
+                BlockHead::decode(&bytes)
             },
             None => {
                 self.set_head(BlockHead::default()).await?;
-                return Ok(BlockHead::default());
+                Ok(BlockHead::default())
             }
         }
     }
     async fn set_head(&self, head: BlockHead) -> Result<()> {
-        let h = self.blobs.add_bytes(head.encode()?).await?.hash;
-        self.tags.set( String::from("head"), h).await?;
+        self.blobs.add_bytes(head.encode()?).with_named_tag(self.tag("head")).await?;
         Ok(())
     }
 
@@ -128,24 +345,111 @@ impl BlockChain {
         match self.get_local_block(hash).await {
             Ok(b) => Ok(b), // We have it locally
             Err(_e) => { // Try to get it from peers
+                // GC's sweep doesn't take the gc_lock, so this keeps the blob until it has a real tag
+                let _protected = self.tags.temp_tag(hash).await?;
                 let s_peers = Shuffled::new(peers);
 
                 let mut progress = self.downloader.download(hash, s_peers)
-                    .stream().await?;
+                    .stream().await.map_err(network("Couldn't start the download"))?;
+
+                while let Some(event) = progress.next().await {
+                    if let DownloadProgressItem::Progress(size) = event && size > CHAIN_CFG.max_block_bytes {
+                        // The downloader gives up on its next progress update once nobody listens
+                        drop(progress);
+                        return Err(self.reject_download("Block too large"));
+                    }
+                }
 
-                while let Some(_event) = progress.next().await {}
-                self.get_local_block(hash).await
+                // Whoever announced bytes that aren't a block is to blame, the caller charges them
+                let block_bytes = self.blobs.get_bytes(hash).await?;
+                if block_bytes.len() as u64 > CHAIN_CFG.max_block_bytes {
+                    return Err(self.reject_download("Block too large"));
+                }
+                let block = Block::decode(&block_bytes).map_err(|_| self.reject_download("Undecodable block"))?;
+                // Downloads aren't tagged, and the store sweeps anything without a tag once _protected is dropped
+                self.tags.create(hash).await?;
+                Ok(block)
             }
         }
     }
 
+    // The rejected blob is never tagged, so the next sweep deletes whatever of it we stored
+    fn reject_download(&self, message: &'static str) -> anyhow::Error {
+        self.sweep.store(true, Ordering::SeqCst);
+        invalid_block(message)
+    }
+
     async fn get_local_block(&self, hash: Hash) -> Result<Block> {
         let block_bytes = self.blobs.get_bytes(hash).await?;
-        Ok(Block::decode(&block_bytes)?)
+        Block::decode(&block_bytes)
+    }
+
+    async fn penalise(&self, peer: EndpointId, offence: Offence) -> Result<()> {
+        let encoded = {
+            let mut peers = self.peers.lock().unwrap();
+            if !peers.penalise(peer, offence) {
+                return Ok(());
+            }
+            peers.encode_bans()?
+        };
+        info!("Banned {} for {:?}", peer, offence);
+        self.peer_book.lock().await.forget(&peer);
+        self.blobs.add_bytes(encoded).with_named_tag("banned").await?;
+        // Every chain on this network drops the peer as a neighbour
+        self.bans.send_replace(());
+        Ok(())
+    }
+
+    async fn save_peer_book(&self) -> Result<()> {
+        let encoded = self.peer_book.lock().await.encode()?;
+        self.blobs.add_bytes(encoded).with_named_tag(self.tag("peer_book")).await?;
+        Ok(())
+    }
+
+    async fn neighbor_up(&self, peer: EndpointId) -> Result<()> {
+        if !self.is_banned(&peer) {
+            self.peer_book.lock().await.neighbor_up(peer);
+            self.save_peer_book().await?;
+        }
+        Ok(())
+    }
+
+    fn is_banned(&self, peer: &EndpointId) -> bool {
+        self.peers.lock().unwrap().is_banned(peer)
+    }
+
+    // Banned peers and peers that said they don't serve blocks are never asked for blocks.
+    // Peers we haven't heard a Hello from are assumed to be full nodes
+    async fn usable_peers(&self, peers: Vec<EndpointId>) -> Vec<EndpointId> {
+        let peers = self.peers.lock().unwrap().filter(peers);
+        let caps = self.peer_caps.lock().await;
+        peers.into_iter()
+            .filter(|p| caps.get(p).is_none_or(|c| c.serves_bodies))
+            .filter(|p| !self.is_cut(p))
+            .collect()
+    }
+
+    // Whether a LocalNet partition sits between us and the peer
+    #[cfg(feature = "test-utils")]
+    fn is_cut(&self, peer: &EndpointId) -> bool {
+        self.local_net.as_ref().is_some_and(|n| n.is_cut(&self.endpoint_id(), peer))
+    }
+
+    #[cfg(not(feature = "test-utils"))]
+    fn is_cut(&self, _peer: &EndpointId) -> bool {
+        false
+    }
+
+    // Holds a message back until the LocalNet's latency has passed since it arrived
+    #[cfg(feature = "test-utils")]
+    async fn local_net_delay(&self, received: time::Instant) {
+        if let Some(net) = &self.local_net {
+            time::sleep(net.latency().saturating_sub(received.elapsed())).await;
+        }
     }
 
     async fn hash_at_height(&self, height: u128) -> Option<Hash>{
-        Some(self.tags.get(height.to_string()).await.ok()??.hash)
+        Some(self.tags.get(self.tag(&height.to_string())).await.ok()??.hash)
     }
 
     async fn add_block_blob(&self, block: Block) -> Result<Hash> {
@@ -156,57 +460,164 @@ impl BlockChain {
 
     async fn temp_add_block(&self, hash: Hash) -> Result<()> {
         let block = self.get_local_block(hash).await?;
-        self.tags.set( String::from("temp_") + &block.block_height.to_string(), hash).await?;
+        self.tags.set(self.tag(&format!("temp_{}", block.block_height)), hash).await?;
         Ok(())
     }
 
     async fn clear_temp_blocks(&self) -> Result<()> {
-        self.tags.delete_prefix(String::from("temp_")).await?;
+        self.tags.delete_prefix(self.tag("temp_")).await?;
+        Ok(())
+    }
+
+    async fn read_journal(&self) -> Result<Option<Journal>> {
+        match self.tags.get(self.tag("journal")).await? {
+            Some(t) => {
+                let bytes = self.blobs.get_bytes(t.hash).await?;
+                Ok(Some(Journal::decode(&bytes)?))
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Once this returns, the reorg will happen even if we crash before apply_journal finishes
+    async fn write_journal(&self, journal: &Journal) -> Result<()> {
+        self.blobs.add_bytes(journal.encode()?).with_named_tag(self.tag("journal")).await?;
         Ok(())
     }
 
-    async fn confirm_block(&self, height: u128) -> Result<()> {
-        self.tags.rename(String::from("temp_") + &height.to_string(), height.to_string()).await?;
+    // Every step only sets tags to fixed values, so running this twice is harmless
+    async fn apply_journal(&self, journal: &Journal) -> Result<()> {
+        for (height, hash) in journal.plan.iter() {
+            self.tags.set(self.tag(&height.to_string()), *hash).await?;
+        }
+        for (name, _) in journal.index_removed.iter() {
+            self.tags.delete(self.tag(name)).await?;
+        }
+        for (name, hash) in journal.index_added.iter() {
+            self.tags.set(self.tag(name), *hash).await?;
+        }
+        self.set_head(journal.new_head.clone()).await?;
+        self.clear_temp_blocks().await?;
+        self.tags.delete(self.tag("journal")).await?;
         Ok(())
     }
 
+    async fn rollback_journal(&self, journal: &Journal) -> Result<()> {
+        for (height, old) in journal.replaced.iter() {
+            match old {
+                Some(hash) => { self.tags.set(self.tag(&height.to_string()), *hash).await?; },
+                None => { self.tags.delete(self.tag(&height.to_string())).await?; },
+            }
+        }
+        for (name, _) in journal.index_added.iter() {
+            self.tags.delete(self.tag(name)).await?;
+        }
+        for (name, hash) in journal.index_removed.iter() {
+            self.tags.set(self.tag(name), *hash).await?;
+        }
+        self.set_head(journal.old_head.clone()).await?;
+        self.clear_temp_blocks().await?;
+        self.tags.delete(self.tag("journal")).await?;
+        Ok(())
+    }
+
+    // Stores from before tags were namespaced by chain hold one chain's head and heights at the top level.
+    // The first chain opened on the store takes them over, unless it already has blocks of its own
+    async fn migrate_legacy_tags(&self) -> Result<()> {
+        let _guard = self.lock_db().await;
+        let mut legacy = Vec::new();
+        let mut tags = self.tags.list().await?;
+        while let Some(tag) = tags.next().await {
+            let name = String::from_utf8_lossy(tag?.name.as_ref()).to_string();
+            if is_legacy_chain_tag(&name) {
+                legacy.push(name);
+            }
+        }
+        if legacy.is_empty() {
+            return Ok(());
+        }
+        if !self.get_head().await?.no_blocks() {
+            info!("Leaving {} tags from an older version alone, this chain already has blocks", legacy.len());
+            return Ok(());
+        }
+
+        info!("Moving {} tags from an older version under chain {}", legacy.len(), self.chain_id);
+        for name in legacy {
+            match name.starts_with("temp_") {
+                // Half-validated blocks, recover would clear them anyway
+                true => { self.tags.delete(&name).await?; },
+                false => { self.tags.rename(&name, self.tag(&name)).await?; },
+            }
+        }
+        Ok(())
+    }
+
+    // Repairs whatever a crash in the middle of new_block left behind
+    async fn recover(&self) -> Result<()> {
+        let _guard = self.lock_db().await;
+        let journal = match self.read_journal().await? {
+            Some(journal) => journal,
+            None => {
+                // Without a journal the head was never touched, these are just a half-validated chain
+                self.clear_temp_blocks().await?;
+                return self.ensure_indexes().await;
+            }
+        };
+
+        // Everything in the plan was validated before the journal was written, so we can finish if the blobs survived
+        let mut have_all = true;
+        for (_, hash) in journal.plan.iter() {
+            if self.get_local_block(*hash).await.is_err() {
+                have_all = false;
+                break;
+            }
+        }
+        if have_all {
+            info!("Finishing an interrupted reorg to height {}", journal.new_head.height);
+            self.apply_journal(&journal).await?;
+        } else {
+            info!("Rolling back an interrupted reorg to height {}", journal.old_head.height);
+            self.rollback_journal(&journal).await?;
+        }
+        self.ensure_indexes().await
+    }
+
     async fn new_block(&self, new_head_hash: Hash, peers: Vec<EndpointId>) -> Result<()> {
         let new_head = self.get_foreign_block(new_head_hash, peers.clone()).await?;
 
         // check that the new block is even worth it, it should be higher than our head
         let head = self.get_head().await?;
         if !head.no_blocks() && (new_head.block_height <= head.height) {
-            return Err(Error::msg("New head is worse than the old one"));
+            return Err(validation("New head is worse than the old one"));
         }
 
         // Loop from the head downwards, validating all those blocks
-        let mut cur_hash = new_head_hash.clone();
-        let mut cur_height = new_head.block_height.clone();
-        let mut block = new_head.clone();
+        let mut cur_hash = new_head_hash;
+        let mut cur_height = new_head.block_height;
+        let mut plan = Vec::new();
         loop {
             // Check block
-            if let Some(h) = self.hash_at_height(cur_height).await {
-                if h == cur_hash {
-                    // We already have this block in our chain, so we can stop
-                    break;
-                }
+            if self.hash_at_height(cur_height).await == Some(cur_hash) {
+                // We already have this block in our chain, so we can stop
+                break;
             }
-            block = self.get_foreign_block(cur_hash, peers.clone()).await?;
+            let block = self.get_foreign_block(cur_hash, peers.clone()).await?;
 
             if cur_height != block.block_height {
-                return Err(Error::msg("Non sequential blocks"));
+                return Err(invalid_block("Non sequential blocks"));
             }
             if cur_height == 0 && block.prev_hash != Hash::EMPTY {
-                return Err(Error::msg("Failed genesis block"));
+                return Err(invalid_block("Failed genesis block"));
             }
 
             // Check replay
             if !self.evaluate_replay(&block).await? {
-                return Err(Error::msg("Replay fail"));
+                return Err(invalid_block("Replay fail"));
             }
 
             // block is verified, add to the temporary storage, wait for lower blocks to be confirmed
             self.temp_add_block(cur_hash).await?;
+            plan.push((cur_height, cur_hash));
 
             // We have reached the genesis block
             if cur_height == 0 {
@@ -217,13 +628,22 @@ impl BlockChain {
         }
 
         // Once all blocks are validated and stored in the temporary area, update it to be our new blockchain
-        for i in (block.block_height)..(new_head.block_height) {
-            self.confirm_block(i).await?;
+        let mut replaced = Vec::new();
+        let mut index_added = Vec::new();
+        let mut index_removed = Vec::new();
+        for (height, hash) in plan.iter() {
+            index_added.extend(index_entries(&self.get_local_block(*hash).await?, *hash));
+            let old = self.hash_at_height(*height).await;
+            if let Some(old) = old {
+                index_removed.extend(index_entries(&self.get_local_block(old).await?, old));
+            }
+            replaced.push((*height, old));
         }
-
         let new_blockhead = BlockHead {hash: new_head_hash, height: new_head.block_height };
-        self.set_head(new_blockhead).await?;
-        self.clear_temp_blocks().await?;
+        let journal = Journal { old_head: head, new_head: new_blockhead, plan, replaced, index_added, index_removed };
+        self.write_journal(&journal).await?;
+        self.apply_journal(&journal).await?;
+        self.head_watch.send_replace(journal.new_head);
 
         let mut new_block_signal = self.new_block_signal.lock().await;
         *new_block_signal = true;
@@ -232,6 +652,10 @@ impl BlockChain {
     }
 
     async fn evaluate_replay(&self, block: &Block) -> Result<bool> {
+        self.replay_wins(block).map_err(game("The game failed while replaying a block"))
+    }
+
+    fn replay_wins(&self, block: &Block) -> Result<bool> {
         let mut game = self.game_gen.create_game()?;
 
         game.set_rng_seed(block.calc_seed())?;
@@ -245,10 +669,9 @@ impl BlockChain {
                 return Ok(false);
             }
 
-            game.step_game(pad)?;
-            let state = game.get_game_state()?;
+            game.step(pad)?;
 
-            if state.has_won() {
+            if game.has_won()? {
                 return Ok(true);
             }
         }
@@ -262,12 +685,8 @@ impl BlockChain {
 
     async fn broadcast_block(&self, hash: Hash) -> Result<()> {
         let message = BlockMessage::NewBlockHead { node: self.node(), hash };
-        let encoded = message.encode()?.to_vec();
-        // sender.broadcast_neighbors(encoded).await?;
-        match self.sender.broadcast_neighbors(encoded.into()).await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(Error::msg("Broadcast block failed")),
-        }
+        let encoded = message.sign(self.chain_id, self.router.endpoint().secret_key())?;
+        self.broadcast(encoded).await.map_err(network("Broadcast block failed"))
     }
 
     async fn broadcast_head(&self) -> Result<()> {
@@ -280,24 +699,43 @@ impl BlockChain {
 
     async fn request_head(&self) -> Result<()> {
         let message = BlockMessage::RequestBlockHead{ node: self.node() };
-        let encoded = message.encode()?.to_vec();
-        match self.sender.broadcast_neighbors(encoded.into()).await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(Error::msg("Request head failed")),
-        }
+        let encoded = message.sign(self.chain_id, self.router.endpoint().secret_key())?;
+        self.broadcast(encoded).await.map_err(network("Request head failed"))
     }
 
-    pub async fn start_mine(&self) {
-        let mut _guard = self.db_lock.lock().await;
-        {
-            // If any new blocks are made while we're playing, then it will kill the recording session
-            let mut new_block_signal = self.new_block_signal.lock().await;
-            *new_block_signal = false;
-        }
+    async fn send_hello(&self) -> Result<()> {
+        let message = BlockMessage::Hello { node: self.node(), version: PROTOCOL_VERSION, capabilities: Capabilities::FULL_NODE };
+        let encoded = message.sign(self.chain_id, self.router.endpoint().secret_key())?;
+        self.broadcast(encoded).await.map_err(network("Hello failed"))
+    }
+
+    async fn send_head_check(&self, to: EndpointId) -> Result<()> {
+        let head = self.get_head().await?;
+        let message = BlockMessage::HeadCheck { node: self.node(), to, head };
+        let encoded = message.sign(self.chain_id, self.router.endpoint().secret_key())?;
+        self.broadcast(encoded).await.map_err(network("Head check failed"))
+    }
+
+    // The head to mine on top of, and a receiver that sees every head after it
+    pub async fn watch_head(&self) -> Result<(BlockHead, watch::Receiver<BlockHead>)> {
+        let _guard = self.lock_db().await;
+        let head = self.get_head().await?;
+        Ok((head, self.head_watch.subscribe()))
     }
 
-    pub async fn submit_mine(&self, new_block: Block) -> Result<()> {
-        let _guard = self.db_lock.lock().await;
+    // Errors are for things going wrong on our side, a bad block is a Rejected outcome.
+    // A block whose parent is no longer the head is kept as a fork block if the parent is at most
+    // max_fork_depth blocks below the head
+    pub async fn submit_mine(&self, new_block: Block, max_fork_depth: u128) -> Result<SubmitOutcome> {
+        let _guard = self.lock_db().await;
+
+        if new_block.solution.len() > CHAIN_CFG.max_solution_time {
+            return Ok(SubmitOutcome::Rejected(RejectReason::TooLong));
+        }
+        let head = self.get_head().await?;
+        if !head.no_blocks() && new_block.block_height <= head.height {
+            return self.submit_fork_block(new_block, head, max_fork_depth).await;
+        }
 
         // Add block to blobs
         let new_hash = self.add_block_blob(new_block).await?;
@@ -310,10 +748,43 @@ impl BlockChain {
             Ok(_) => {
                 self.broadcast_block(new_hash).await?;
                 self.print_state().await?;
+                Ok(SubmitOutcome::AcceptedAsHead)
             },
-            Err(_) => {info!("Submitting block failed")}
+            // Our own block builds on our head, so the only way it can be invalid is a failed replay
+            Err(e) if is_invalid_block(&e) => {
+                info!("Submitted block failed validation: {}", e);
+                Ok(SubmitOutcome::Rejected(RejectReason::ReplayFailed))
+            },
+            Err(e) => Err(e),
         }
-        Ok(())
+    }
+
+    async fn submit_fork_block(&self, new_block: Block, head: BlockHead, max_fork_depth: u128) -> Result<SubmitOutcome> {
+        // How many blocks the head is above the parent. A genesis block's "parent" sits below height 0
+        let depth = match new_block.block_height.checked_sub(1) {
+            Some(parent_height) => head.height - parent_height,
+            None => head.height.saturating_add(1),
+        };
+        // The parent has to be a block we hold at the height below, or depth means nothing
+        let parent_fits = match new_block.block_height.checked_sub(1) {
+            Some(parent_height) => self.get_local_block(new_block.prev_hash).await
+                .is_ok_and(|parent| parent.block_height == parent_height),
+            None => new_block.prev_hash == Hash::EMPTY,
+        };
+        if depth > max_fork_depth || !parent_fits {
+            info!("Submitted block at height {} is {} behind our head", new_block.block_height, depth);
+            return Ok(SubmitOutcome::Rejected(RejectReason::StaleParent));
+        }
+        if !self.evaluate_replay(&new_block).await? {
+            return Ok(SubmitOutcome::Rejected(RejectReason::ReplayFailed));
+        }
+
+        // Not announced: it's no higher than our head, so anyone who has seen our head would reject it.
+        // GC keeps it while it's within fork_window of a head. If a chain built on it ever beats our head,
+        // new_block finds it here instead of downloading it
+        let new_hash = self.add_block_blob(new_block).await?;
+        info!("Submitted block {} as a fork {} below our head", new_hash, depth);
+        Ok(SubmitOutcome::AcceptedAsSideChain)
     }
 
     pub async fn has_new_block(&self) -> bool {
@@ -327,115 +798,342 @@ impl BlockChain {
     }
     // these two have _public because we shouldn't use them in this file. because we don't want to acquire locks
     pub async fn get_head_public(&self) -> Result<BlockHead> {
-        let _guard = self.db_lock.lock().await;
+        let _guard = self.lock_db().await;
         let head = self.get_head().await?;
         Ok(head)
     }
 
     pub async fn get_local_block_public(&self, hash: Hash) -> Result<Block> {
-        let _guard = self.db_lock.lock().await;
+        let _guard = self.lock_db().await;
         self.get_local_block(hash).await
     }
+
+    // The canonical block at height, None if our chain isn't that long
+    // Downloads a block as if it had been announced, for tests that serve blobs by hand
+    #[cfg(feature = "test-utils")]
+    pub async fn get_foreign_block_public(&self, hash: Hash, peers: Vec<EndpointId>) -> Result<Block> {
+        let _guard = self.lock_db().await;
+        self.get_foreign_block(hash, peers).await
+    }
+
+    // Whether the store holds any of the blob, even a partial download
+    #[cfg(feature = "test-utils")]
+    pub async fn holds_blob(&self, hash: Hash) -> Result<bool> {
+        Ok(!matches!(self.blobs.status(hash).await?, BlobStatus::NotFound))
+    }
+
+    pub async fn get_block_at_height_public(&self, height: u128) -> Result<Option<Block>> {
+        let _guard = self.lock_db().await;
+        match self.hash_at_height(height).await {
+            Some(hash) => Ok(Some(self.get_local_block(hash).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // The canonical blocks in range, cut off at our head. Read under one lock so a reorg can't mix two chains
+    pub async fn get_blocks_public(&self, range: RangeInclusive<u128>) -> Result<Vec<Block>> {
+        let _guard = self.lock_db().await;
+        let mut blocks = Vec::new();
+        for height in range {
+            match self.hash_at_height(height).await {
+                Some(hash) => blocks.push(self.get_local_block(hash).await?),
+                None => break,
+            }
+        }
+        Ok(blocks)
+    }
 }
 
-async fn subscribe_loop(
-    bc: BlockChain, mut receiver: GossipReceiver
-) {
-    info!("SUBLOOP: Waiting to connect...");
+// Keeps us subscribed to the topic, re-subscribing with backoff whenever the gossip stream dies
+async fn gossip_supervisor<G: GameVerifier>(bc: BlockChain<G>, mut receiver: GossipReceiver) {
+    // Downloads and replays happen on their own task so a flood of messages can't stall the receiver
+    let (work_tx, work_rx) = mpsc::channel(WORK_QUEUE_SIZE);
+    task::spawn(work_loop(bc.clone(), work_rx));
+    let mut limiter = RateLimiter::default();
+    let mut replay_guard = ReplayGuard::default();
+    let mut backoff = MIN_BACKOFF;
+
     loop {
-        if init_connection(&bc, &mut receiver).await.is_ok() {
-            break;
+        info!("SUBLOOP: Waiting to connect...");
+        bc.set_connection_state(ConnectionState::Joining).await;
+        match init_connection(&bc, &mut receiver).await {
+            Ok(_) => {
+                bc.set_connection_state(ConnectionState::Connected).await;
+                backoff = MIN_BACKOFF;
+                subscribe_loop(&bc, &mut receiver, &mut limiter, &mut replay_guard, &work_tx).await;
+                info!("SUBLOOP: Gossip stream ended");
+            },
+            Err(e) => info!("SUBLOOP: Failed to connect: {}", e.to_string()),
+        }
+        bc.set_connection_state(ConnectionState::Disconnected).await;
+        // Dropping the receiver leaves the topic, which closes our connections to the old neighbours
+        drop(receiver);
+
+        loop {
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            match bc.resubscribe().await {
+                Ok(r) => {
+                    receiver = r;
+                    break;
+                },
+                Err(e) => info!("SUBLOOP: Re-subscribing failed: {}", e.to_string()),
+            }
         }
     }
-    info!("SUBLOOP: Connected!");
+}
 
-    while let Some(e) = receiver.next().await {
+async fn subscribe_loop<G: GameVerifier>(
+    bc: &BlockChain<G>, receiver: &mut GossipReceiver, limiter: &mut RateLimiter, replay_guard: &mut ReplayGuard,
+    work_tx: &mpsc::Sender<Work>
+) {
+    let mut bans = bc.bans.subscribe();
+    loop {
+        // There's no way to drop a single neighbour, so a ban makes us leave and rejoin without it
+        let next = async { receiver.next().await.map(Some) };
+        let banned = async { bans.changed().await.ok().map(|_| None) };
+        let Some(e) = next.or(banned).await else { break };
+        let Some(e) = e else { // Someone got banned
+            match receiver.neighbors().any(|p| bc.is_banned(&p)) {
+                true => {info!("SUBLOOP: Leaving to drop a banned neighbour"); break;},
+                false => continue,
+            }
+        };
         if e.is_err() {info!("SUB_LOOP API ERROR"); continue;}
         let event = e.unwrap();
-        match process_event(&bc, &mut receiver, event).await {
+        match process_event(bc, receiver, limiter, replay_guard, work_tx, event).await {
             Ok(_) => {},
             Err(e) => info!("SUB_LOOP ERROR: {}", e.to_string()),
         }
     }
 }
 
-async fn init_connection(bc: &BlockChain, receiver: &mut GossipReceiver) -> Result<()> {
+async fn discovery_loop<G: GameVerifier>(bc: BlockChain<G>, discovery: Discovery) {
+    loop {
+        match discover(&bc, &discovery).await {
+            Ok(_) => {},
+            Err(e) => info!("DISCOVERY ERROR: {}", e.to_string()),
+        }
+        time::sleep(DISCOVERY_INTERVAL).await;
+    }
+}
+
+async fn discover<G: GameVerifier>(bc: &BlockChain<G>, discovery: &Discovery) -> Result<()> {
+    discovery.publish(bc.chain_id, bc.endpoint_id()).await?;
+
+    let found = discovery.lookup(bc.chain_id).await?;
+    let mut peers = bc.usable_peers(found).await;
+    peers.retain(|p| *p != bc.endpoint_id());
+    if !peers.is_empty() {
+        info!("Discovered {} peers", peers.len());
+        let sender = bc.sender.lock().await.clone();
+        sender.join_peers(peers).await?;
+    }
+    Ok(())
+}
+
+// Catches us (or a neighbour) up if a NewBlockHead message went missing
+async fn anti_entropy_loop<G: GameVerifier>(bc: BlockChain<G>) {
+    let mut round: u32 = 0;
+    loop {
+        time::sleep(ANTI_ENTROPY_INTERVAL).await;
+        if bc.connection_state().await != ConnectionState::Connected {
+            continue;
+        }
+        round = round.wrapping_add(1);
+
+        match anti_entropy_round(&bc, round).await {
+            Ok(_) => {},
+            Err(e) => info!("ANTI_ENTROPY ERROR: {}", e.to_string()),
+        }
+    }
+}
+
+async fn anti_entropy_round<G: GameVerifier>(bc: &BlockChain<G>, round: u32) -> Result<()> {
+    let _guard = bc.lock_db().await;
+    if round.is_multiple_of(REANNOUNCE_EVERY) {
+        bc.broadcast_head().await?;
+    }
+
+    let neighbors = bc.usable_peers(bc.live_peers().await).await;
+    // The thread rng can't be held across an await
+    let to = neighbors.choose(&mut rand::rng()).copied();
+    if let Some(to) = to {
+        bc.send_head_check(to).await?;
+    }
+    Ok(())
+}
+
+async fn init_connection<G: GameVerifier>(bc: &BlockChain<G>, receiver: &mut GossipReceiver) -> Result<()> {
     receiver.joined().await?;
+    // joined() swallows the NeighborUp events of our first neighbours
+    for peer in receiver.neighbors() {
+        bc.neighbor_up(peer).await?;
+    }
     {
-        let _guard = bc.db_lock.lock().await;
+        let _guard = bc.lock_db().await;
+        bc.send_hello().await?;
         bc.request_head().await?;
         bc.broadcast_head().await?;
     }
     Ok(())
 }
 
-async fn process_event(bc: &BlockChain, receiver: &mut GossipReceiver, event: Event) -> Result<()> {
-    let _guard = bc.db_lock.lock().await;
+struct Work {
+    sender: EndpointId,
+    message: BlockMessage,
+    neighbors: Vec<EndpointId>,
+    #[cfg(feature = "test-utils")]
+    received: time::Instant,
+}
+
+async fn process_event<G: GameVerifier>(
+    bc: &BlockChain<G>, receiver: &mut GossipReceiver, limiter: &mut RateLimiter, replay_guard: &mut ReplayGuard,
+    work_tx: &mpsc::Sender<Work>, event: Event
+) -> Result<()> {
     info!("Event received!");
 
     if let Event::Received(msg) = event {
+        let sender = msg.delivered_from;
+        if bc.is_cut(&sender) {
+            return Ok(());
+        }
+        if bc.is_banned(&sender) {
+            return Err(validation("Message from banned peer"));
+        }
+        if !limiter.allow(sender) {
+            return Err(validation("Peer is sending too fast"));
+        }
         match msg.scope {
             Neighbors => {} // Only accept direct neighbour messages
-            Swarm(_) => {return Err(Error::msg("Bad message scope"));}
-        }
-        let message = BlockMessage::decode(&msg.content)?;
-        match message {
-            BlockMessage::NewBlockHead { hash, node: _ } => {
-                // info!("Message: New Block Head");
-                let peers: Vec<EndpointId> = receiver.neighbors().into_iter().collect();   
-                bc.new_block(hash, peers).await?;
-                bc.broadcast_head().await?;
-                bc.print_state().await?;
+            Swarm(_) => {
+                bc.penalise(sender, Offence::SwarmSpam).await?;
+                return Err(validation("Bad message scope"));
+            }
+        }
+        let message = match open_message(&msg.content, sender, bc.chain_id) {
+            Ok(Some(m)) => m,
+            Ok(None) => {
+                info!("Skipping message from a newer protocol version");
+                return Ok(());
             },
-            BlockMessage::RequestBlockHead { node: _ } => {
-                // info!("Message: Request Block Head");
-                bc.broadcast_head().await?;
+            Err(e) => {
+                bc.penalise(sender, Offence::BadMessage).await?;
+                return Err(e);
             }
+        };
+        replay_guard.check(message.node())?;
+
+        // Handshakes don't touch the chain so they skip the work queue
+        if let BlockMessage::Hello { version, capabilities, .. } = message {
+            info!("Hello from {} (protocol v{}, {:?})", sender, version, capabilities);
+            bc.peer_caps.lock().await.insert(sender, capabilities);
+            return Ok(());
+        }
+        let neighbors: Vec<EndpointId> = receiver.neighbors().collect();
+        let work = Work {
+            sender, message, neighbors,
+            #[cfg(feature = "test-utils")]
+            received: time::Instant::now(),
+        };
+        if work_tx.try_send(work).is_err() {
+            return Err(validation("Work queue full, dropping message"));
         }
     }
-    else if let Event::NeighborUp(key) = event {info!("Joined {}", key);}
-    else if let Event::NeighborDown(key) = event {info!("Downed {}", key);}
+    else if let Event::NeighborUp(key) = event {
+        info!("Joined {}", key);
+        bc.neighbor_up(key).await?;
+        bc.send_hello().await?;
+    }
+    else if let Event::NeighborDown(key) = event {
+        info!("Downed {}", key);
+        limiter.forget(&key);
+        bc.peer_caps.lock().await.remove(&key);
+        bc.peer_book.lock().await.neighbor_down(&key);
+    }
     else if let Event::Lagged = event {info!("Lagged");};
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Node {
-    endpoint_id: EndpointId,
-    timestamp: DateTime<Utc>,
-}
-
-impl Node {
-    pub fn new(endpoint_id: EndpointId) -> Self {
-        Node {endpoint_id, timestamp: Utc::now() }
+async fn work_loop<G: GameVerifier>(bc: BlockChain<G>, mut work_rx: mpsc::Receiver<Work>) {
+    while let Some(work) = work_rx.recv().await {
+        #[cfg(feature = "test-utils")]
+        bc.local_net_delay(work.received).await;
+        match process_work(&bc, work).await {
+            Ok(_) => {},
+            Err(e) => info!("WORK_LOOP ERROR: {}", e.to_string()),
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum BlockMessage {
-    NewBlockHead { node: Node, hash: Hash },
-    RequestBlockHead { node: Node },
-}
+async fn process_work<G: GameVerifier>(bc: &BlockChain<G>, work: Work) -> Result<()> {
+    let _guard = bc.lock_db().await;
+    let Work { sender, message, neighbors, .. } = work;
+
+    match message {
+        BlockMessage::NewBlockHead { hash, node: _ } => {
+            // info!("Message: New Block Head");
+            let peers = bc.usable_peers(neighbors).await;
+            if let Err(e) = bc.new_block(hash, peers).await {
+                if is_invalid_block(&e) {
+                    bc.penalise(sender, Offence::InvalidBlock).await?;
+                }
+                return Err(e);
+            }
+            bc.peers.lock().unwrap().reward(sender);
+            bc.broadcast_head().await?;
+            bc.print_state().await?;
+        },
+        BlockMessage::RequestBlockHead { node: _ } => {
+            // info!("Message: Request Block Head");
+            bc.broadcast_head().await?;
+        }
+        BlockMessage::Hello { .. } => {} // Handled in process_event
+        BlockMessage::HeadCheck { to, head: their_head, node: _ } => {
+            if to != bc.endpoint_id() {
+                return Ok(());
+            }
+            let head = bc.get_head().await?;
+            if their_head.hash == head.hash {
+                return Ok(());
+            }
 
-impl BlockMessage {
-    pub fn decode(bytes: &[u8]) -> Result<BlockMessage> {
-        Ok(postcard::from_bytes(bytes)?)
+            if !their_head.no_blocks() && (head.no_blocks() || their_head.height > head.height) {
+                info!("Anti-entropy: {} is ahead of us, syncing", sender);
+                let mut peers = vec![sender];
+                peers.extend(neighbors);
+                let peers = bc.usable_peers(peers).await;
+                if let Err(e) = bc.new_block(their_head.hash, peers).await {
+                    if is_invalid_block(&e) {
+                        bc.penalise(sender, Offence::InvalidBlock).await?;
+                    }
+                    return Err(e);
+                }
+                bc.print_state().await?;
+            }
+            // Either they are behind, or we were and just caught up. Both ways our head is worth announcing
+            bc.broadcast_head().await?;
+        }
     }
+    Ok(())
+}
 
-    fn encode(&self) -> Result<Bytes> {
-        Ok(postcard::to_stdvec(&self)?.into())
+async fn load_peer_book(net: &Network, chain_id: TopicId) -> Result<Option<PeerBook>> {
+    match net.tags.get(chain_tag(chain_id, "peer_book")).await? {
+        Some(t) => {
+            let bytes = net.blobs.get_bytes(t.hash).await?;
+            Ok(Some(PeerBook::decode(&bytes)?))
+        },
+        None => Ok(None),
     }
 }
 
-#[cfg(feature = "fs")]
-async fn load_store() -> Store {
-    let store_path = String::from("blockchain_data");
-    iroh_blobs::store::fs::FsStore::load(store_path)
-        .await
-        .expect("failed to load fs").into()
+// The chain tags older versions kept at the top level: the head, a tag per height and temp_ ones during a sync
+fn is_legacy_chain_tag(name: &str) -> bool {
+    name == "head" || name.starts_with("temp_") || (!name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()))
 }
 
-#[cfg(not(feature = "fs"))]
-async fn load_store() -> Store {
-    MemStore::new().into()
-}
\ No newline at end of file
+// Every chain's tags live under its own prefix so chains sharing a store can't clobber each other
+fn chain_tag(chain_id: TopicId, name: &str) -> String {
+    format!("{}/{}", hex::encode(chain_id.as_bytes()), name)
+}
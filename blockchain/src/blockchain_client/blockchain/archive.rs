@@ -0,0 +1,154 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::ops::RangeInclusive;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use iroh_blobs::Hash;
+use iroh_gossip::TopicId;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::CHAIN_CFG;
+use crate::verifier::GameVerifier;
+use crate::error::validation;
+use super::{Block, BlockChain};
+
+// Archive layout: MAGIC, then a length-prefixed Manifest, then `count` length-prefixed encoded blocks
+// in ascending height order. Lengths are u32 big endian
+const MAGIC: &[u8; 8] = b"SM64CHN\0";
+const ARCHIVE_VERSION: u16 = 1;
+const MAX_MANIFEST_BYTES: u32 = 4096;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u16,
+    chain_id: TopicId,
+    first_height: u128,
+    last_height: u128,
+    count: u64,
+    // Hash of the last block, so a truncated archive is caught before anything is imported
+    last_hash: Hash,
+    created: DateTime<Utc>,
+}
+
+// The archive is the caller's file, so failing to read or write it isn't a storage error
+fn read_error(err: io::Error) -> anyhow::Error {
+    match err.kind() {
+        ErrorKind::UnexpectedEof => validation("Archive is truncated or corrupt"),
+        _ => validation(format!("Couldn't read the archive: {}", err)),
+    }
+}
+
+fn write_error(err: io::Error) -> anyhow::Error {
+    validation(format!("Couldn't write the archive: {}", err))
+}
+
+fn write_chunk(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes()).map_err(write_error)?;
+    writer.write_all(bytes).map_err(write_error)?;
+    Ok(())
+}
+
+fn read_chunk(reader: &mut impl Read, max_len: u32) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).map_err(read_error)?;
+    let len = u32::from_be_bytes(len);
+    if len > max_len {
+        return Err(validation("Archive chunk is too large"));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes).map_err(read_error)?;
+    Ok(bytes)
+}
+
+impl<G: GameVerifier> BlockChain<G> {
+    // Writes the canonical blocks in `range` (the whole chain if None), returns how many were written.
+    // The end of the range is clamped to our head
+    pub async fn export_archive(&self, range: Option<RangeInclusive<u128>>, mut writer: impl Write) -> Result<u64> {
+        let _guard = self.lock_db().await;
+        let head = self.get_head().await?;
+        if head.no_blocks() {
+            return Err(validation("There are no blocks to export"));
+        }
+        let range = range.map_or(0..=head.height, |r| *r.start()..=(*r.end()).min(head.height));
+        if range.is_empty() {
+            return Err(validation("Export range is outside the chain"));
+        }
+
+        let last_hash = self.hash_at_height(*range.end()).await
+            .ok_or_else(|| validation("Missing block in canonical chain"))?;
+        let manifest = Manifest {
+            version: ARCHIVE_VERSION,
+            chain_id: self.chain_id,
+            first_height: *range.start(),
+            last_height: *range.end(),
+            count: (range.end() - range.start() + 1) as u64,
+            last_hash,
+            created: Utc::now(),
+        };
+        writer.write_all(MAGIC).map_err(write_error)?;
+        write_chunk(&mut writer, &postcard::to_stdvec(&manifest)?)?;
+
+        for height in range {
+            let hash = self.hash_at_height(height).await
+                .ok_or_else(|| validation("Missing block in canonical chain"))?;
+            write_chunk(&mut writer, &self.blobs.get_bytes(hash).await?)?;
+        }
+        writer.flush().map_err(write_error)?;
+        Ok(manifest.count)
+    }
+
+    // Adds every block in the archive, then replays them all like a block from the network.
+    // An archive that doesn't start at genesis must connect to our chain, or to a neighbour's
+    pub async fn import_archive(&self, mut reader: impl Read) -> Result<u64> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(|_| validation("Not a chain archive"))?;
+        if &magic != MAGIC {
+            return Err(validation("Not a chain archive"));
+        }
+        let manifest: Manifest = postcard::from_bytes(&read_chunk(&mut reader, MAX_MANIFEST_BYTES)?)
+            .map_err(|_| validation("Archive manifest is corrupt"))?;
+        if manifest.version != ARCHIVE_VERSION {
+            return Err(validation("Unsupported archive version"));
+        }
+        // Anyone can write a manifest, so the heights can't be trusted not to overflow
+        let last_height = manifest.count.checked_sub(1)
+            .and_then(|n| manifest.first_height.checked_add(n as u128));
+        if last_height != Some(manifest.last_height) {
+            return Err(validation("Archive manifest is inconsistent"));
+        }
+        if manifest.chain_id != self.chain_id {
+            info!("Importing an archive from chain {} into {}", manifest.chain_id, self.chain_id);
+        }
+
+        // Read and check everything before touching the store
+        let mut chunks = Vec::new();
+        let mut prev_hash: Option<Hash> = None;
+        for i in 0..manifest.count {
+            let bytes = read_chunk(&mut reader, CHAIN_CFG.max_block_bytes as u32)?;
+            let block = Block::decode(&bytes).map_err(|_| validation("Archive contains an undecodable block"))?;
+            if manifest.first_height.checked_add(i as u128) != Some(block.block_height) {
+                return Err(validation("Archive blocks are out of order"));
+            }
+            if prev_hash.is_some_and(|h| h != block.prev_hash) {
+                return Err(validation("Archive blocks don't link up"));
+            }
+            prev_hash = Some(Hash::new(&bytes));
+            chunks.push(bytes);
+        }
+        if prev_hash != Some(manifest.last_hash) {
+            return Err(validation("Archive is truncated or corrupt"));
+        }
+
+        // Added as they are, so the hashes match the ones we just checked
+        let _guard = self.lock_db().await;
+        for bytes in chunks {
+            self.blobs.add_bytes(bytes).await?;
+        }
+        let peers = self.usable_peers(self.live_peers().await).await;
+        self.new_block(manifest.last_hash, peers).await?;
+        self.broadcast_head().await?;
+        self.print_state().await?;
+        Ok(manifest.count)
+    }
+}
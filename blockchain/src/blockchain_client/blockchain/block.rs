@@ -1,12 +1,12 @@
-use std::u128;
 use bytes::Bytes;
-use anyhow::{Result, Error};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 use iroh_blobs::Hash;
 use chrono::{DateTime, Utc};
 
 use crate::CHAIN_CFG;
+use crate::error::validation;
 use sm64_binds::{GamePad, RngConfig};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,7 +22,7 @@ pub struct Block {
 impl Block {
     pub fn new(block_head: BlockHead, miner_name: String) -> Result<Self> {
         if miner_name.len() > CHAIN_CFG.max_name_length {
-            return Err(Error::msg("Miner name is too long"));
+            return Err(validation("Miner name is too long"));
         }
 
         let prev_hash = block_head.hash;
@@ -34,7 +34,7 @@ impl Block {
     
     pub fn seal(&mut self, solution_vec: Vec<GamePad>) -> Result<()> {
         if solution_vec.len() > CHAIN_CFG.max_solution_time {
-            return Err(Error::msg("Solution is too long"));
+            return Err(validation("Solution is too long"));
         }
         self.solution = solution_vec;
         Ok(())
@@ -64,7 +64,7 @@ impl Block {
 
     pub fn calc_rng_config(&self) -> RngConfig {
         // might be calculated later using block difficulty etc
-        return RngConfig::default();
+        RngConfig::default()
     }
 
     pub fn encode(&self) -> Result<Bytes> {
@@ -74,10 +74,10 @@ impl Block {
     pub fn decode(bytes: &[u8]) -> Result<Block> {
         let block: Block = postcard::from_bytes(bytes)?;
         if block.miner_name.len() > CHAIN_CFG.max_name_length {
-            return Err(Error::msg("Miner name is too long"));
+            return Err(validation("Miner name is too long"));
         };
         if block.solution.len() > CHAIN_CFG.max_solution_time {
-            return Err(Error::msg("Solution is too long"));
+            return Err(validation("Solution is too long"));
         }
         Ok(block)
     }
@@ -0,0 +1,372 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use arrow_array::builder::{
+    BooleanBuilder, Float32Builder, Int8Builder, Int32Builder, ListBuilder, StringBuilder,
+    TimestampMillisecondBuilder, UInt16Builder, UInt32Builder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{Field, Schema};
+use iroh_blobs::Hash;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::trace::{Trace, TraceFrame};
+use crate::verifier::GameVerifier;
+use crate::error::validation;
+use super::{Block, BlockChain};
+
+// A dataset is a directory of Arrow IPC files with one row (episode) per canonical block, plus a manifest
+// listing them in height order. Every run appends the blocks mined since the last one, after dropping
+// any files a reorg has replaced
+const MANIFEST_FILE: &str = "manifest.json";
+const DATASET_VERSION: u16 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u16,
+    // Whether the files have the per-frame state columns. Every file in a dataset has the same columns
+    traces: bool,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Part {
+    file: String,
+    first_height: u128,
+    last_height: u128,
+    // If this isn't the canonical block at last_height any more, the file is out of date
+    last_hash: Hash,
+}
+
+// The dataset directory is the caller's, so failing to read or write it isn't a storage error
+fn read_error(err: io::Error) -> anyhow::Error {
+    validation(format!("Couldn't read the dataset: {}", err))
+}
+
+fn write_error(err: impl fmt::Display) -> anyhow::Error {
+    validation(format!("Couldn't write the dataset: {}", err))
+}
+
+// Files are only touched through std::fs, which blocks, so it happens off the async threads
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f).await?
+}
+
+impl Manifest {
+    async fn load(dir: PathBuf, traces: bool) -> Result<Manifest> {
+        let path = dir.join(MANIFEST_FILE);
+        let bytes = blocking(move || {
+            fs::create_dir_all(&dir).map_err(write_error)?;
+            match path.exists() {
+                true => Ok(Some(fs::read(path).map_err(read_error)?)),
+                false => Ok(None),
+            }
+        }).await?;
+        let Some(bytes) = bytes else {
+            return Ok(Manifest { version: DATASET_VERSION, traces, parts: Vec::new() });
+        };
+        let manifest: Manifest = serde_json::from_slice(&bytes).map_err(|_| validation("Dataset manifest is corrupt"))?;
+        if manifest.version != DATASET_VERSION {
+            return Err(validation("Unsupported dataset version"));
+        }
+        if manifest.traces != traces {
+            return Err(validation("The dataset was started with a different traces setting, use a new directory"));
+        }
+        Ok(manifest)
+    }
+
+    async fn save(&self, dir: &Path) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(self)?;
+        let (tmp, path) = (dir.join(format!("{}.tmp", MANIFEST_FILE)), dir.join(MANIFEST_FILE));
+        blocking(move || {
+            fs::write(&tmp, bytes).map_err(write_error)?;
+            fs::rename(tmp, path).map_err(write_error)?;
+            Ok(())
+        }).await
+    }
+
+    fn next_height(&self) -> u128 {
+        self.parts.last().map_or(0, |p| p.last_height + 1)
+    }
+}
+
+// One column per field, lists hold a value per frame
+struct Columns {
+    height: UInt64Builder,
+    hash: StringBuilder,
+    prev_hash: StringBuilder,
+    timestamp: TimestampMillisecondBuilder,
+    miner: StringBuilder,
+    seed: UInt32Builder,
+    rng_window_length: UInt32Builder,
+    rng_random_amount: UInt32Builder,
+    rng_random_burst_length: UInt32Builder,
+    rng_a_prob: Float32Builder,
+    rng_b_prob: Float32Builder,
+    rng_z_prob: Float32Builder,
+    frames: UInt32Builder,
+    won: BooleanBuilder,
+    win_frame: UInt32Builder,
+    stars: Int32Builder,
+    button: ListBuilder<UInt16Builder>,
+    stick_x: ListBuilder<Int8Builder>,
+    stick_y: ListBuilder<Int8Builder>,
+    traces: Option<TraceColumns>,
+}
+
+struct TraceColumns {
+    pos: [ListBuilder<Float32Builder>; 3],
+    vel: [ListBuilder<Float32Builder>; 3],
+    course_num: ListBuilder<Int32Builder>,
+    act_num: ListBuilder<Int32Builder>,
+    area_index: ListBuilder<Int32Builder>,
+    num_stars: ListBuilder<Int32Builder>,
+}
+
+impl TraceColumns {
+    fn new() -> Self {
+        TraceColumns {
+            pos: std::array::from_fn(|_| ListBuilder::new(Float32Builder::new())),
+            vel: std::array::from_fn(|_| ListBuilder::new(Float32Builder::new())),
+            course_num: ListBuilder::new(Int32Builder::new()),
+            act_num: ListBuilder::new(Int32Builder::new()),
+            area_index: ListBuilder::new(Int32Builder::new()),
+            num_stars: ListBuilder::new(Int32Builder::new()),
+        }
+    }
+
+    fn append(&mut self, frames: &[TraceFrame]) {
+        for frame in frames {
+            for i in 0..3 {
+                self.pos[i].values().append_value(frame.pos[i]);
+                self.vel[i].values().append_value(frame.vel[i]);
+            }
+            self.course_num.values().append_value(frame.course_num);
+            self.act_num.values().append_value(frame.act_num);
+            self.area_index.values().append_value(frame.area_index);
+            self.num_stars.values().append_value(frame.num_stars);
+        }
+        self.pos.iter_mut().chain(self.vel.iter_mut()).for_each(|l| l.append(true));
+        self.course_num.append(true);
+        self.act_num.append(true);
+        self.area_index.append(true);
+        self.num_stars.append(true);
+    }
+
+    fn finish(mut self, columns: &mut Vec<(&'static str, ArrayRef, bool)>) {
+        let [pos_x, pos_y, pos_z] = self.pos.each_mut().map(|l| Arc::new(l.finish()) as ArrayRef);
+        let [vel_x, vel_y, vel_z] = self.vel.each_mut().map(|l| Arc::new(l.finish()) as ArrayRef);
+        columns.extend([
+            ("pos_x", pos_x, false),
+            ("pos_y", pos_y, false),
+            ("pos_z", pos_z, false),
+            ("vel_x", vel_x, false),
+            ("vel_y", vel_y, false),
+            ("vel_z", vel_z, false),
+            ("course_num", Arc::new(self.course_num.finish()) as ArrayRef, false),
+            ("act_num", Arc::new(self.act_num.finish()) as ArrayRef, false),
+            ("area_index", Arc::new(self.area_index.finish()) as ArrayRef, false),
+            ("num_stars", Arc::new(self.num_stars.finish()) as ArrayRef, false),
+        ]);
+    }
+}
+
+impl Columns {
+    fn new(traces: bool) -> Self {
+        Columns {
+            height: UInt64Builder::new(),
+            hash: StringBuilder::new(),
+            prev_hash: StringBuilder::new(),
+            timestamp: TimestampMillisecondBuilder::new().with_timezone("UTC"),
+            miner: StringBuilder::new(),
+            seed: UInt32Builder::new(),
+            rng_window_length: UInt32Builder::new(),
+            rng_random_amount: UInt32Builder::new(),
+            rng_random_burst_length: UInt32Builder::new(),
+            rng_a_prob: Float32Builder::new(),
+            rng_b_prob: Float32Builder::new(),
+            rng_z_prob: Float32Builder::new(),
+            frames: UInt32Builder::new(),
+            won: BooleanBuilder::new(),
+            win_frame: UInt32Builder::new(),
+            stars: Int32Builder::new(),
+            button: ListBuilder::new(UInt16Builder::new()),
+            stick_x: ListBuilder::new(Int8Builder::new()),
+            stick_y: ListBuilder::new(Int8Builder::new()),
+            traces: traces.then(TraceColumns::new),
+        }
+    }
+
+    // frames is the replay of the block, which stops at the winning frame
+    fn append(&mut self, hash: Hash, block: &Block, frames: &[TraceFrame]) -> Result<()> {
+        let height = u64::try_from(block.block_height).map_err(|_| validation("Block height is too large for the dataset"))?;
+        let rng = block.calc_rng_config();
+        let won = frames.last().is_some_and(|f| f.won);
+
+        self.height.append_value(height);
+        self.hash.append_value(hash.to_string());
+        self.prev_hash.append_value(block.prev_hash.to_string());
+        self.timestamp.append_value(block.timestamp.timestamp_millis());
+        self.miner.append_value(&block.miner_name);
+        self.seed.append_value(block.calc_seed());
+        self.rng_window_length.append_value(rng.window_length);
+        self.rng_random_amount.append_value(rng.random_amount);
+        self.rng_random_burst_length.append_value(rng.random_burst_length);
+        self.rng_a_prob.append_value(rng.a_prob);
+        self.rng_b_prob.append_value(rng.b_prob);
+        self.rng_z_prob.append_value(rng.z_prob);
+        self.frames.append_value(block.solution.len() as u32);
+        self.won.append_value(won);
+        self.win_frame.append_option(won.then(|| frames.len() as u32 - 1));
+        self.stars.append_value(frames.last().map_or(0, |f| f.num_stars));
+        for pad in block.solution.iter() {
+            self.button.values().append_value(pad.button);
+            self.stick_x.values().append_value(pad.stick_x);
+            self.stick_y.values().append_value(pad.stick_y);
+        }
+        self.button.append(true);
+        self.stick_x.append(true);
+        self.stick_y.append(true);
+        if let Some(traces) = self.traces.as_mut() {
+            traces.append(frames);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<RecordBatch> {
+        let mut columns: Vec<(&'static str, ArrayRef, bool)> = vec![
+            ("height", Arc::new(self.height.finish()), false),
+            ("hash", Arc::new(self.hash.finish()), false),
+            ("prev_hash", Arc::new(self.prev_hash.finish()), false),
+            ("timestamp", Arc::new(self.timestamp.finish()), false),
+            ("miner", Arc::new(self.miner.finish()), false),
+            ("seed", Arc::new(self.seed.finish()), false),
+            ("rng_window_length", Arc::new(self.rng_window_length.finish()), false),
+            ("rng_random_amount", Arc::new(self.rng_random_amount.finish()), false),
+            ("rng_random_burst_length", Arc::new(self.rng_random_burst_length.finish()), false),
+            ("rng_a_prob", Arc::new(self.rng_a_prob.finish()), false),
+            ("rng_b_prob", Arc::new(self.rng_b_prob.finish()), false),
+            ("rng_z_prob", Arc::new(self.rng_z_prob.finish()), false),
+            ("frames", Arc::new(self.frames.finish()), false),
+            ("won", Arc::new(self.won.finish()), false),
+            ("win_frame", Arc::new(self.win_frame.finish()), true),
+            ("stars", Arc::new(self.stars.finish()), false),
+            ("button", Arc::new(self.button.finish()), false),
+            ("stick_x", Arc::new(self.stick_x.finish()), false),
+            ("stick_y", Arc::new(self.stick_y.finish()), false),
+        ];
+        if let Some(traces) = self.traces {
+            traces.finish(&mut columns);
+        }
+
+        let fields: Vec<Field> = columns.iter()
+            .map(|(name, array, nullable)| Field::new(*name, array.data_type().clone(), *nullable))
+            .collect();
+        let arrays = columns.into_iter().map(|(_, array, _)| array).collect();
+        Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+    }
+}
+
+impl<G: GameVerifier> BlockChain<G> {
+    // Brings the dataset in `dir` up to our head, writing at most blocks_per_file episodes to each new file.
+    // Returns how many episodes were written. Safe to interrupt, the next run carries on from the last whole file
+    pub async fn export_dataset(&self, dir: &Path, traces: bool, blocks_per_file: u64) -> Result<u64> {
+        if blocks_per_file == 0 {
+            return Err(validation("blocks_per_file must be at least 1"));
+        }
+        let mut manifest = Manifest::load(dir.to_path_buf(), traces).await?;
+        let mut count = 0;
+
+        loop {
+            self.drop_replaced_parts(dir, &mut manifest).await?;
+            let first_height = manifest.next_height();
+            let batch = self.dataset_batch(first_height, blocks_per_file).await?;
+            let Some((last_hash, last)) = batch.last() else {
+                break;
+            };
+            // The chain can reorg between reading two batches. Going round again drops whatever it replaced
+            if let Some(part) = manifest.parts.last() && batch[0].1.prev_hash != part.last_hash {
+                continue;
+            }
+
+            let part = Part {
+                file: format!("blocks-{:020}-{:020}.arrow", first_height, last.block_height),
+                first_height,
+                last_height: last.block_height,
+                last_hash: *last_hash,
+            };
+            let episodes = batch.len() as u64;
+            // Replaying every block can take minutes, so it stays off the async threads
+            let (game_gen, path) = (self.game_gen.clone(), dir.join(&part.file));
+            blocking(move || write_dataset_file(&game_gen, &path, &batch, traces)).await?;
+            info!("Wrote {} episodes to {}", episodes, part.file);
+            manifest.parts.push(part);
+            manifest.save(dir).await?;
+
+            count += episodes;
+            if episodes < blocks_per_file {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    // Newest first, deletes the files whose last block isn't canonical any more
+    async fn drop_replaced_parts(&self, dir: &Path, manifest: &mut Manifest) -> Result<()> {
+        while let Some(part) = manifest.parts.last() {
+            let canonical = {
+                let _guard = self.lock_db().await;
+                self.hash_at_height(part.last_height).await
+            };
+            if canonical == Some(part.last_hash) {
+                break;
+            }
+            info!("Dropping {}, the chain has reorganised since it was written", part.file);
+            let path = dir.join(&part.file);
+            blocking(move || {
+                let _ = fs::remove_file(path);
+                Ok(())
+            }).await?;
+            manifest.parts.pop();
+            manifest.save(dir).await?;
+        }
+        Ok(())
+    }
+
+    // Up to `max` canonical blocks from first_height, read under one lock so they're all from the same chain
+    async fn dataset_batch(&self, first_height: u128, max: u64) -> Result<Vec<(Hash, Block)>> {
+        let _guard = self.lock_db().await;
+        let mut batch = Vec::new();
+        for height in (first_height..).take(max as usize) {
+            match self.hash_at_height(height).await {
+                Some(hash) => batch.push((hash, self.get_local_block(hash).await?)),
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+}
+
+// Written next to the real name and renamed into place, so a file listed in the manifest is always whole
+fn write_dataset_file<G: GameVerifier>(game_gen: &G, path: &Path, batch: &[(Hash, Block)], traces: bool) -> Result<()> {
+    let mut columns = Columns::new(traces);
+    for (hash, block) in batch {
+        let frames = Trace::new(game_gen, block)?.collect::<crate::Result<Vec<_>>>()?;
+        columns.append(*hash, block, &frames)?;
+    }
+    let record_batch = columns.finish()?;
+
+    let tmp = path.with_extension("arrow.tmp");
+    let file = File::create(&tmp).map_err(write_error)?;
+    let mut writer = FileWriter::try_new(BufWriter::new(file), &record_batch.schema()).map_err(write_error)?;
+    writer.write(&record_batch).map_err(write_error)?;
+    writer.finish().map_err(write_error)?;
+    fs::rename(tmp, path).map_err(write_error)?;
+    Ok(())
+}
@@ -0,0 +1,170 @@
+#[cfg(any(feature = "dht", feature = "test-utils"))]
+use std::collections::BTreeMap;
+#[cfg(feature = "test-utils")]
+use std::collections::HashMap;
+#[cfg(feature = "test-utils")]
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+#[cfg(any(feature = "dht", feature = "test-utils"))]
+use chrono::Utc;
+use iroh::EndpointId;
+use iroh_gossip::TopicId;
+#[cfg(any(feature = "dht", feature = "test-utils"))]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "dht")]
+use crate::error::network;
+#[cfg(feature = "dht")]
+use sha2::{Digest, Sha256};
+
+// Records older than this are assumed to be offline nodes
+#[cfg(any(feature = "dht", feature = "test-utils"))]
+const RECORD_TTL_SECS: i64 = 30 * 60;
+// Keeps a record under the 1000 byte BEP44 limit
+#[cfg(any(feature = "dht", feature = "test-utils"))]
+const MAX_RECORD_PEERS: usize = 16;
+// Every topic is spread over this many DHT records so one full record doesn't hide everyone else
+#[cfg(feature = "dht")]
+const SLOTS: u8 = 8;
+
+// Where to find other nodes on a topic without passing tickets around.
+// Without the dht feature there's nothing to choose, outside of tests
+#[derive(Debug, Clone)]
+pub enum Discovery {
+    #[cfg(feature = "dht")]
+    Mainline(mainline::async_dht::AsyncDht),
+    #[cfg(feature = "test-utils")]
+    Local(LocalDht),
+}
+
+impl Discovery {
+    #[cfg(feature = "dht")]
+    pub fn mainline() -> Result<Self> {
+        let dht = mainline::Dht::client().map_err(network("Couldn't start the DHT client"))?;
+        Ok(Discovery::Mainline(dht.as_async()))
+    }
+
+    #[cfg_attr(not(any(feature = "dht", feature = "test-utils")), allow(unused_variables))]
+    pub async fn publish(&self, topic_id: TopicId, endpoint_id: EndpointId) -> Result<()> {
+        match *self {
+            #[cfg(feature = "dht")]
+            Discovery::Mainline(ref dht) => mainline_publish(dht, topic_id, endpoint_id).await,
+            #[cfg(feature = "test-utils")]
+            Discovery::Local(ref dht) => {
+                dht.publish(topic_id, endpoint_id);
+                Ok(())
+            },
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "dht", feature = "test-utils")), allow(unused_variables))]
+    pub async fn lookup(&self, topic_id: TopicId) -> Result<Vec<EndpointId>> {
+        match *self {
+            #[cfg(feature = "dht")]
+            Discovery::Mainline(ref dht) => mainline_lookup(dht, topic_id).await,
+            #[cfg(feature = "test-utils")]
+            Discovery::Local(ref dht) => Ok(dht.lookup(topic_id)),
+        }
+    }
+}
+
+// In-process stand-in for the DHT, clones share the same records
+#[cfg(feature = "test-utils")]
+#[derive(Debug, Clone, Default)]
+pub struct LocalDht {
+    records: Arc<Mutex<HashMap<TopicId, Record>>>,
+}
+
+#[cfg(feature = "test-utils")]
+impl LocalDht {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn publish(&self, topic_id: TopicId, endpoint_id: EndpointId) {
+        let mut records = self.records.lock().expect("poisoned");
+        records.entry(topic_id).or_default().add(endpoint_id);
+    }
+
+    fn lookup(&self, topic_id: TopicId) -> Vec<EndpointId> {
+        let records = self.records.lock().expect("poisoned");
+        records.get(&topic_id).map(|r| r.fresh_peers()).unwrap_or_default()
+    }
+}
+
+// Who announced themselves on a topic and when (unix seconds)
+#[cfg(any(feature = "dht", feature = "test-utils"))]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Record {
+    peers: BTreeMap<EndpointId, i64>,
+}
+
+#[cfg(any(feature = "dht", feature = "test-utils"))]
+impl Record {
+    fn add(&mut self, endpoint_id: EndpointId) {
+        let now = Utc::now().timestamp();
+        self.peers.retain(|_, t| now - *t < RECORD_TTL_SECS);
+        self.peers.insert(endpoint_id, now);
+
+        while self.peers.len() > MAX_RECORD_PEERS {
+            let oldest = self.peers.iter().min_by_key(|(_, t)| **t).map(|(p, _)| *p);
+            match oldest {
+                Some(p) => self.peers.remove(&p),
+                None => break,
+            };
+        }
+    }
+
+    fn fresh_peers(&self) -> Vec<EndpointId> {
+        let now = Utc::now().timestamp();
+        self.peers.iter()
+            .filter(|(_, t)| now - **t < RECORD_TTL_SECS)
+            .map(|(p, _)| *p)
+            .collect()
+    }
+}
+
+// Every node can derive the keys for a topic's records, so anyone on the topic can write to them
+#[cfg(feature = "dht")]
+fn slot_key(topic_id: TopicId, slot: u8) -> mainline::SigningKey {
+    let mut hasher = Sha256::new();
+    hasher.update(b"sm64-crypto discovery");
+    hasher.update(topic_id.as_bytes());
+    hasher.update([slot]);
+    mainline::SigningKey::from_bytes(&hasher.finalize().into())
+}
+
+#[cfg(feature = "dht")]
+async fn mainline_publish(dht: &mainline::async_dht::AsyncDht, topic_id: TopicId, endpoint_id: EndpointId) -> Result<()> {
+    let slot = rand::random::<u8>() % SLOTS;
+    let key = slot_key(topic_id, slot);
+
+    let previous = dht.get_mutable_most_recent(key.verifying_key().as_bytes(), None).await;
+    let (mut record, cas) = match previous {
+        Some(item) => (postcard::from_bytes(item.value()).unwrap_or_default(), Some(item.seq())),
+        None => (Record::default(), None),
+    };
+    record.add(endpoint_id);
+
+    let seq = cas.map_or(0, |s| s + 1);
+    let item = mainline::MutableItem::new(key, &postcard::to_stdvec(&record)?, seq, None);
+    dht.put_mutable(item, cas).await.map_err(network("Couldn't publish to the DHT"))?;
+    Ok(())
+}
+
+#[cfg(feature = "dht")]
+async fn mainline_lookup(dht: &mainline::async_dht::AsyncDht, topic_id: TopicId) -> Result<Vec<EndpointId>> {
+    let mut peers = Vec::new();
+    for slot in 0..SLOTS {
+        let key = slot_key(topic_id, slot);
+        if let Some(item) = dht.get_mutable_most_recent(key.verifying_key().as_bytes(), None).await {
+            // Anyone can write these records, so skip the ones that don't parse
+            if let Ok(record) = postcard::from_bytes::<Record>(item.value()) {
+                peers.extend(record.fresh_peers());
+            }
+        }
+    }
+    peers.sort();
+    peers.dedup();
+    Ok(peers)
+}
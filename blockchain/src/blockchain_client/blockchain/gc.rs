@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use futures_lite::StreamExt;
+use iroh_blobs::Hash;
+use tracing::info;
+
+use crate::verifier::GameVerifier;
+use super::{Block, BlockChain, BlockHead};
+
+// What the store names the tags add_bytes and create make. Those protect nothing we need, every tag
+// we set ourselves (or don't recognise, like an older version's) keeps its blob
+const AUTO_TAG_PREFIX: &str = "auto-";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    // Blobs still referenced by a chain (blocks, heads, journals, peer books)
+    pub kept: usize,
+    // Side-chain blocks kept because they are within the fork window
+    pub kept_forks: usize,
+    pub deleted: usize,
+    pub reclaimed_bytes: u64,
+}
+
+impl<G: GameVerifier> BlockChain<G> {
+    // Deletes every blob that isn't reachable from a chain's tags, except for blocks within
+    // fork_window of a head, which might still win a reorg. This covers every chain sharing the store.
+    // The blobs are untagged here and removed from disk by the store's next sweep
+    pub async fn collect_garbage(&self, fork_window: u128) -> Result<GcStats> {
+        let _gc_guard = self.gc_lock.write().await;
+        let mut stats = GcStats::default();
+
+        let mut live: HashSet<Hash> = HashSet::new();
+        let mut auto_tags = Vec::new();
+        let mut heads: Vec<BlockHead> = Vec::new();
+        let mut tags = self.tags.list().await?;
+        while let Some(tag) = tags.next().await {
+            let tag = tag?;
+            let name = String::from_utf8_lossy(tag.name.as_ref()).to_string();
+            if name.starts_with(AUTO_TAG_PREFIX) {
+                auto_tags.push((tag.name, tag.hash));
+                continue;
+            }
+            if name.ends_with("/head") {
+                let head = BlockHead::decode(&self.blobs.get_bytes(tag.hash).await?)?;
+                if !head.no_blocks() {
+                    heads.push(head);
+                }
+            }
+            live.insert(tag.hash);
+        }
+        stats.kept = live.len();
+
+        let auto_tagged: HashSet<Hash> = auto_tags.iter().map(|(_, h)| *h).collect();
+        let mut dead: HashSet<Hash> = HashSet::new();
+        for hash in self.blobs.list().hashes().await? {
+            if live.contains(&hash) {
+                continue;
+            }
+            let bytes = match self.blobs.get_bytes(hash).await {
+                Ok(bytes) => bytes,
+                Err(_) => continue, // Partial downloads aren't ours to clean up
+            };
+            if let Ok(block) = Block::decode(&bytes) {
+                // A chain with no head yet could be syncing any of these
+                let near_a_head = heads.is_empty() || heads.iter()
+                    .any(|h| block.block_height.saturating_add(fork_window) >= h.height);
+                if near_a_head {
+                    // The sweep would take it with the dead blobs if nothing tags it
+                    if !auto_tagged.contains(&hash) {
+                        self.tags.create(hash).await?;
+                    }
+                    stats.kept_forks += 1;
+                    continue;
+                }
+            }
+            stats.reclaimed_bytes += bytes.len() as u64;
+            dead.insert(hash);
+        }
+
+        // Untagged blobs are deleted by the store's next sweep
+        for (name, hash) in auto_tags {
+            if dead.contains(&hash) {
+                self.tags.delete(name).await?;
+            }
+        }
+        stats.deleted = dead.len();
+        self.sweep.store(true, Ordering::SeqCst);
+
+        info!("GC: deleted {} blobs ({} bytes), kept {} plus {} fork blocks",
+            stats.deleted, stats.reclaimed_bytes, stats.kept, stats.kept_forks);
+        Ok(stats)
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures_lite::StreamExt;
+use iroh_blobs::Hash;
+
+use crate::verifier::GameVerifier;
+use super::{Block, BlockChain};
+
+// Index tags sit next to the height tags and point at the block itself:
+//   miner/{hex(miner_name)}/{height}
+//   time/{unix millis}/{height}
+// Numbers are zero padded so tags sort in numeric order and ranges can be listed directly
+const MINER_PREFIX: &str = "miner/";
+const TIME_PREFIX: &str = "time/";
+const INDEX_BUILT: &str = "index_built";
+
+fn miner_prefix(miner_name: &str) -> String {
+    format!("{}{}/", MINER_PREFIX, hex::encode(miner_name))
+}
+
+fn time_key(timestamp: DateTime<Utc>) -> String {
+    // Blocks from before 1970 are sorted as if they were mined in 1970
+    format!("{}{:020}/", TIME_PREFIX, timestamp.timestamp_millis().max(0))
+}
+
+// The index entries for a block at a height, as (name without the chain prefix, block hash)
+pub fn index_entries(block: &Block, hash: Hash) -> Vec<(String, Hash)> {
+    vec![
+        (format!("{}{:039}", miner_prefix(&block.miner_name), block.block_height), hash),
+        (format!("{}{:039}", time_key(block.timestamp), block.block_height), hash),
+    ]
+}
+
+impl<G: GameVerifier> BlockChain<G> {
+    // Builds the indexes for a chain that was synced before they existed
+    pub(super) async fn ensure_indexes(&self) -> Result<()> {
+        if self.tags.get(self.tag(INDEX_BUILT)).await?.is_some() {
+            return Ok(());
+        }
+        self.tags.delete_prefix(self.tag(MINER_PREFIX)).await?;
+        self.tags.delete_prefix(self.tag(TIME_PREFIX)).await?;
+
+        let mut height = 0;
+        while let Some(hash) = self.hash_at_height(height).await {
+            let block = self.get_local_block(hash).await?;
+            for (name, hash) in index_entries(&block, hash) {
+                self.tags.set(self.tag(&name), hash).await?;
+            }
+            height += 1;
+        }
+        self.blobs.add_bytes(&b"1"[..]).with_named_tag(self.tag(INDEX_BUILT)).await?;
+        Ok(())
+    }
+
+    async fn indexed_blocks(&self, tags: Vec<Hash>) -> Result<Vec<Block>> {
+        let mut blocks = Vec::with_capacity(tags.len());
+        for hash in tags {
+            blocks.push(self.get_local_block(hash).await?);
+        }
+        Ok(blocks)
+    }
+
+    // Every canonical block mined under this name, lowest first
+    pub async fn blocks_by_miner_public(&self, miner_name: &str) -> Result<Vec<Block>> {
+        let _guard = self.lock_db().await;
+        let mut hashes = Vec::new();
+        let mut tags = self.tags.list_prefix(self.tag(&miner_prefix(miner_name))).await?;
+        while let Some(tag) = tags.next().await {
+            hashes.push(tag?.hash);
+        }
+        self.indexed_blocks(hashes).await
+    }
+
+    // Every canonical block with a timestamp in [from, to), oldest first
+    pub async fn blocks_between_public(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Block>> {
+        let _guard = self.lock_db().await;
+        let mut hashes = Vec::new();
+        let range = self.tag(&time_key(from))..self.tag(&time_key(to));
+        let mut tags = self.tags.list_range(range).await?;
+        while let Some(tag) = tags.next().await {
+            hashes.push(tag?.hash);
+        }
+        self.indexed_blocks(hashes).await
+    }
+}
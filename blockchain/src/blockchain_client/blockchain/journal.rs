@@ -0,0 +1,31 @@
+use bytes::Bytes;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use iroh_blobs::Hash;
+
+use super::BlockHead;
+
+// Everything a reorg is about to change, written before touching any height tags.
+// If we crash halfway, startup recovery either finishes it or puts everything back
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Journal {
+    pub old_head: BlockHead,
+    pub new_head: BlockHead,
+    // The validated blocks of the new chain, by height
+    pub plan: Vec<(u128, Hash)>,
+    // What those heights pointed at before, None if they were above our old head
+    pub replaced: Vec<(u128, Option<Hash>)>,
+    // Index tags (without the chain prefix) for the new blocks, and the ones for the blocks they replace
+    pub index_added: Vec<(String, Hash)>,
+    pub index_removed: Vec<(String, Hash)>,
+}
+
+impl Journal {
+    pub fn encode(&self) -> Result<Bytes> {
+        Ok(postcard::to_stdvec(&self)?.into())
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Journal> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+}
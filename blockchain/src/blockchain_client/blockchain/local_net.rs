@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+
+use iroh::{discovery::static_provider::StaticProvider, Endpoint, EndpointAddr, EndpointId, RelayMode, SecretKey};
+use n0_future::time::Duration;
+
+use crate::error::network;
+
+// In-process stand-in for the internet, for running many nodes in one test. Endpoints only listen on
+// localhost, have no relay and find each other through a shared address book. Clones share the same network.
+// Cut links still carry traffic, the nodes just drop each other's messages and don't download from each other.
+// That keeps the gossip mesh intact, so a healed partition behaves like one that never lost its connections
+#[derive(Debug, Clone, Default)]
+pub struct LocalNet {
+    addrs: StaticProvider,
+    links: Arc<Mutex<Links>>,
+}
+
+#[derive(Debug, Default)]
+struct Links {
+    // Which side of a partition each node is on, nodes that aren't listed are on side 0
+    sides: HashMap<EndpointId, usize>,
+    next_side: usize,
+    latency: Duration,
+}
+
+impl LocalNet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Cuts these nodes off from everyone else. Nodes isolated together can still reach each other
+    pub fn isolate(&self, nodes: &[EndpointId]) {
+        let mut links = self.links.lock().expect("poisoned");
+        links.next_side += 1;
+        let side = links.next_side;
+        for node in nodes {
+            links.sides.insert(*node, side);
+        }
+    }
+
+    // Joins every partition back together
+    pub fn heal(&self) {
+        self.links.lock().expect("poisoned").sides.clear();
+    }
+
+    // Every gossip message is handled this long after it arrives
+    pub fn set_latency(&self, latency: Duration) {
+        self.links.lock().expect("poisoned").latency = latency;
+    }
+
+    pub(super) fn is_cut(&self, a: &EndpointId, b: &EndpointId) -> bool {
+        let links = self.links.lock().expect("poisoned");
+        links.sides.get(a).unwrap_or(&0) != links.sides.get(b).unwrap_or(&0)
+    }
+
+    pub(super) fn latency(&self) -> Duration {
+        self.links.lock().expect("poisoned").latency
+    }
+
+    // Nodes get their endpoint from here, tests can also bind bare ones to talk to nodes directly
+    pub async fn bind(&self, secret_key: SecretKey) -> crate::Result<Endpoint> {
+        let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+            .secret_key(secret_key)
+            .bind_addr_v4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+            .discovery(self.addrs.clone())
+            .bind().await
+            .map_err(network("Couldn't bind the endpoint"))?;
+
+        let mut addr = EndpointAddr::new(endpoint.id());
+        for socket in endpoint.bound_sockets().into_iter().filter(|s| s.is_ipv4()) {
+            addr = addr.with_ip_addr(socket);
+        }
+        self.addrs.add_endpoint_info(addr);
+        Ok(endpoint)
+    }
+}
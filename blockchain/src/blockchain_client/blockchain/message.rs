@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+
+use bytes::Bytes;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, TimeDelta, Utc};
+use iroh::{EndpointId, SecretKey, Signature};
+use iroh_blobs::Hash;
+use iroh_gossip::TopicId;
+
+use super::BlockHead;
+use crate::error::validation;
+
+// Messages older than this (or this far in the future) are dropped
+const MAX_MESSAGE_AGE_SECS: i64 = 60;
+
+// Bump when BlockMessage changes. Older nodes skip messages from newer versions instead of failing
+pub const PROTOCOL_VERSION: u16 = 2;
+const MIN_PROTOCOL_VERSION: u16 = 1;
+
+// What a node is willing to do for its neighbours, advertised in BlockMessage::Hello
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub serves_headers: bool,
+    pub serves_bodies: bool,
+    pub light_client: bool,
+}
+
+impl Capabilities {
+    pub const FULL_NODE: Capabilities = Capabilities {
+        serves_headers: true,
+        serves_bodies: true,
+        light_client: false,
+    };
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Node {
+    pub endpoint_id: EndpointId,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Node {
+    pub fn new(endpoint_id: EndpointId) -> Self {
+        Node {endpoint_id, timestamp: Utc::now() }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BlockMessage {
+    NewBlockHead { node: Node, hash: Hash },
+    RequestBlockHead { node: Node },
+    Hello { node: Node, version: u16, capabilities: Capabilities },
+    // Anti-entropy: only `to` acts on this, by syncing or answering with its own head
+    HeadCheck { node: Node, to: EndpointId, head: BlockHead },
+}
+
+impl BlockMessage {
+    pub fn node(&self) -> &Node {
+        match self {
+            BlockMessage::NewBlockHead { node, .. } => node,
+            BlockMessage::RequestBlockHead { node } => node,
+            BlockMessage::Hello { node, .. } => node,
+            BlockMessage::HeadCheck { node, .. } => node,
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<BlockMessage> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+
+    pub fn encode(&self) -> Result<Bytes> {
+        Ok(postcard::to_stdvec(&self)?.into())
+    }
+
+    // Wraps the message so receivers can check it really came from us
+    pub fn sign(&self, chain_id: TopicId, secret_key: &SecretKey) -> Result<Bytes> {
+        let envelope = Envelope { version: PROTOCOL_VERSION, chain_id, message: self.encode()?.to_vec() };
+        let payload = postcard::to_stdvec(&envelope)?;
+        let signature = secret_key.sign(&payload).to_bytes();
+        SignedMessage { payload, signature }.encode()
+    }
+}
+
+// Never change the layout of this, it is how nodes of different versions understand each other
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    version: u16,
+    chain_id: TopicId,
+    message: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedMessage {
+    payload: Vec<u8>,
+    #[serde(with = "serde_arrays")]
+    signature: [u8; 64],
+}
+
+impl SignedMessage {
+    fn encode(&self) -> Result<Bytes> {
+        Ok(postcard::to_stdvec(&self)?.into())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<SignedMessage> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+}
+
+// Checks the signature belongs to the peer that delivered the message, then decodes it.
+// Returns None for messages from a newer protocol that we don't understand
+pub fn open_message(bytes: &[u8], delivered_from: EndpointId, chain_id: TopicId) -> Result<Option<BlockMessage>> {
+    let signed = SignedMessage::decode(bytes)?;
+    let signature = Signature::from_bytes(&signed.signature);
+    delivered_from.verify(&signed.payload, &signature)
+        .map_err(|_| validation("Bad message signature"))?;
+
+    let envelope: Envelope = postcard::from_bytes(&signed.payload)?;
+    if envelope.chain_id != chain_id {
+        return Err(validation("Message is for another chain"));
+    }
+    if envelope.version < MIN_PROTOCOL_VERSION {
+        return Err(validation("Protocol version is too old"));
+    }
+
+    let message = match BlockMessage::decode(&envelope.message) {
+        Ok(m) => m,
+        Err(_) if envelope.version > PROTOCOL_VERSION => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if message.node().endpoint_id != delivered_from {
+        return Err(validation("Message claims to be from another node"));
+    }
+    Ok(Some(message))
+}
+
+// Remembers recent messages so the same one can't be delivered twice
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    seen: HashSet<(EndpointId, DateTime<Utc>)>,
+}
+
+impl ReplayGuard {
+    pub fn check(&mut self, node: &Node) -> Result<()> {
+        let now = Utc::now();
+        let max_age = TimeDelta::seconds(MAX_MESSAGE_AGE_SECS);
+        if (now - node.timestamp).abs() > max_age {
+            return Err(validation("Stale message"));
+        }
+
+        // Anything older than max_age would be rejected above anyway
+        self.seen.retain(|(_, t)| now - *t <= max_age);
+        if !self.seen.insert((node.endpoint_id, node.timestamp)) {
+            return Err(validation("Replayed message"));
+        }
+        Ok(())
+    }
+}
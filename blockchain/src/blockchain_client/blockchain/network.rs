@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use iroh::{protocol::{AccessLimit, ProtocolHandler, Router}, Endpoint, SecretKey};
+use iroh_blobs::{api::{downloader::Downloader, tags::Tags, Store}, store::{mem::MemStore, GcConfig, ProtectOutcome}, BlobsProtocol};
+use iroh_gossip::{net::Gossip, TopicId};
+use n0_future::time::Duration;
+use tokio::sync::{watch, RwLock};
+
+#[cfg(feature = "test-utils")]
+use super::local_net::LocalNet;
+use super::peers::PeerScores;
+use crate::error::{config, network};
+
+// How often the store checks whether a sweep was asked for
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+// Where a node keeps its blobs and tags
+#[derive(Debug, Clone)]
+pub enum Storage {
+    // A directory on disk (needs the fs feature)
+    Path(PathBuf),
+    // Lost on exit, handy for tests and browsers
+    Memory,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        match cfg!(feature = "fs") {
+            true => Storage::Path(PathBuf::from("blockchain_data")),
+            false => Storage::Memory,
+        }
+    }
+}
+
+// The endpoint, store and protocols of a node. Every chain the node follows shares one of these
+#[derive(Debug, Clone)]
+pub struct Network {
+    pub(super) router: Router,
+    pub(super) downloader: Downloader,
+    pub(super) blobs: BlobsProtocol,
+    pub(super) tags: Tags,
+    pub(super) gossip: Gossip,
+    // A peer misbehaving on one chain isn't welcome on the others
+    pub(super) peers: Arc<Mutex<PeerScores>>,
+    // Bumped whenever a peer is banned, so every chain drops it as a neighbour
+    pub(super) bans: Arc<watch::Sender<()>>,
+    // Chains hold this for reading while they work, garbage collection holds it for writing
+    pub(super) gc_lock: Arc<RwLock<()>>,
+    // Set by garbage collection so the store's next sweep deletes every blob without a tag
+    pub(super) sweep: Arc<AtomicBool>,
+    // Set when the node runs on an in-process network instead of the internet
+    #[cfg(feature = "test-utils")]
+    pub(super) local_net: Option<LocalNet>,
+}
+
+impl Network {
+    pub async fn new(storage: Storage, persist_key: bool) -> Result<Self> {
+        let secret_key = load_secret_key(&storage, persist_key)?;
+        let endpoint = Endpoint::builder().secret_key(secret_key).bind().await
+            .map_err(network("Couldn't bind the endpoint"))?;
+        Self::with_endpoint(endpoint, storage).await
+    }
+
+    // A node on an in-process network, with a new identity every time
+    #[cfg(feature = "test-utils")]
+    pub async fn on_local_net(storage: Storage, local_net: LocalNet) -> Result<Self> {
+        let endpoint = local_net.bind(random_secret_key()).await?;
+        let mut net = Self::with_endpoint(endpoint, storage).await?;
+        net.local_net = Some(local_net);
+        Ok(net)
+    }
+
+    async fn with_endpoint(endpoint: Endpoint, storage: Storage) -> Result<Self> {
+        let sweep = Arc::new(AtomicBool::new(false));
+        let store = load_store(&storage, sweep_config(sweep.clone())).await?;
+
+        let blobs = BlobsProtocol::new(&store, None);
+        let gossip = Gossip::builder().spawn(endpoint.clone());
+        let tags = blobs.tags().clone();
+        let downloader = store.downloader(&endpoint);
+
+        // Setup router
+        let peers = Arc::new(Mutex::new(PeerScores::default()));
+        let router = Router::builder(endpoint.clone())
+            .accept(iroh_blobs::ALPN, refuse_banned(blobs.clone(), &peers))
+            .accept(iroh_gossip::ALPN, refuse_banned(gossip.clone(), &peers))
+            .spawn();
+
+        let bans = Arc::new(watch::channel(()).0);
+        let gc_lock = Arc::new(RwLock::new(()));
+        let net = Network {
+            router, downloader, blobs, tags, gossip, peers, bans, gc_lock, sweep,
+            #[cfg(feature = "test-utils")]
+            local_net: None,
+        };
+        net.load_bans().await?;
+        Ok(net)
+    }
+
+    async fn load_bans(&self) -> Result<()> {
+        if let Some(t) = self.tags.get(String::from("banned")).await? {
+            let bytes = self.blobs.get_bytes(t.hash).await?;
+            self.peers.lock().unwrap().load_bans(&bytes)?;
+        }
+        Ok(())
+    }
+
+    // The topic we were last on, so we can rejoin it without a ticket
+    pub(super) async fn last_topic(&self) -> Result<Option<TopicId>> {
+        match self.tags.get(String::from("last_topic")).await? {
+            Some(t) => {
+                let bytes = self.blobs.get_bytes(t.hash).await?;
+                Ok(Some(postcard::from_bytes(&bytes)?))
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub(super) async fn set_last_topic(&self, topic_id: TopicId) -> Result<()> {
+        self.blobs.add_bytes(postcard::to_stdvec(&topic_id)?).with_named_tag("last_topic").await?;
+        Ok(())
+    }
+}
+
+// Banned peers can't download from us or connect to us through gossip
+fn refuse_banned<P: ProtocolHandler + Clone>(proto: P, peers: &Arc<Mutex<PeerScores>>) -> AccessLimit<P> {
+    let peers = Arc::clone(peers);
+    AccessLimit::new(proto, move |peer| !peers.lock().unwrap().is_banned(&peer))
+}
+
+// The store can only delete blobs in its own sweeps, which remove everything that isn't tagged.
+// We skip them unless garbage collection has just untagged the blobs it wants gone. A sweep runs without
+// the gc_lock, so anything mid-download is held by a temp tag until it gets a real one
+fn sweep_config(sweep: Arc<AtomicBool>) -> GcConfig {
+    GcConfig {
+        interval: SWEEP_INTERVAL,
+        add_protected: Some(Arc::new(move |_live| {
+            let outcome = match sweep.swap(false, Ordering::SeqCst) {
+                true => ProtectOutcome::Continue,
+                false => ProtectOutcome::Abort,
+            };
+            Box::pin(async move { outcome })
+        })),
+    }
+}
+
+fn random_secret_key() -> SecretKey {
+    SecretKey::from_bytes(&rand::random())
+}
+
+// Keeps our EndpointId the same across restarts, so old tickets still point at us
+fn load_secret_key(storage: &Storage, persist: bool) -> Result<SecretKey> {
+    if !persist {
+        return Ok(random_secret_key());
+    }
+    match storage {
+        Storage::Path(path) => load_secret_key_file(path),
+        Storage::Memory => Err(config("Persistent node identity needs on-disk storage")),
+    }
+}
+
+#[cfg(feature = "fs")]
+fn load_secret_key_file(store_path: &Path) -> Result<SecretKey> {
+    use std::io::Write;
+
+    let key_path = store_path.join("secret_key");
+    if let Ok(bytes) = std::fs::read(&key_path) {
+        let bytes: [u8; 32] = bytes.try_into().map_err(|_| config("Secret key file is corrupt"))?;
+        return Ok(SecretKey::from_bytes(&bytes));
+    }
+
+    let secret_key = random_secret_key();
+    std::fs::create_dir_all(store_path)?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600); // Only we should be able to read our key
+    }
+    let mut file = options.open(&key_path)?;
+    file.write_all(&secret_key.to_bytes())?;
+    tracing::info!("Saved new node identity to {}", key_path.display());
+    Ok(secret_key)
+}
+
+#[cfg(not(feature = "fs"))]
+fn load_secret_key_file(_store_path: &Path) -> Result<SecretKey> {
+    Err(config("Persistent node identity needs the fs feature"))
+}
+
+fn mem_store(gc: GcConfig) -> MemStore {
+    MemStore::new_with_opts(iroh_blobs::store::mem::Options { gc_config: Some(gc) })
+}
+
+#[cfg(feature = "fs")]
+async fn load_store(storage: &Storage, gc: GcConfig) -> Result<Store> {
+    use iroh_blobs::store::fs::{options::Options, FsStore};
+
+    match storage {
+        Storage::Path(path) => {
+            let options = Options { gc: Some(gc), ..Options::new(path) };
+            Ok(FsStore::load_with_opts(path.join("blobs.db"), options).await?.into())
+        },
+        Storage::Memory => Ok(mem_store(gc).into()),
+    }
+}
+
+#[cfg(not(feature = "fs"))]
+async fn load_store(storage: &Storage, gc: GcConfig) -> Result<Store> {
+    match storage {
+        Storage::Path(_) => Err(config("On-disk storage needs the fs feature")),
+        Storage::Memory => Ok(mem_store(gc).into()),
+    }
+}
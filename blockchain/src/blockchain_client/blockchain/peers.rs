@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use iroh::EndpointId;
+use iroh_gossip::TopicId;
+use n0_future::time::Instant;
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+
+// Once a peer's penalties add up to this, we ban it
+const BAN_THRESHOLD: u32 = 100;
+
+// How many peers the address book remembers, the least recently seen are forgotten first
+const MAX_KNOWN_PEERS: usize = 64;
+
+// Each peer may burst this many messages, then gets one more per second
+const RATE_BURST: f64 = 10.0;
+const RATE_PER_SEC: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Offence {
+    InvalidBlock,
+    BadMessage,
+    SwarmSpam,
+}
+
+impl Offence {
+    fn penalty(&self) -> u32 {
+        match self {
+            Offence::InvalidBlock => 50,
+            Offence::BadMessage => 20,
+            Offence::SwarmSpam => 10,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PeerScores {
+    penalties: HashMap<EndpointId, u32>,
+    banned: BTreeSet<EndpointId>,
+}
+
+impl PeerScores {
+    pub fn is_banned(&self, peer: &EndpointId) -> bool {
+        self.banned.contains(peer)
+    }
+
+    // Returns true if this offence is what got the peer banned
+    pub fn penalise(&mut self, peer: EndpointId, offence: Offence) -> bool {
+        if self.is_banned(&peer) {
+            return false;
+        }
+        let penalty = self.penalties.entry(peer).or_insert(0);
+        *penalty = penalty.saturating_add(offence.penalty());
+
+        if *penalty >= BAN_THRESHOLD {
+            self.penalties.remove(&peer);
+            self.banned.insert(peer);
+            return true;
+        }
+        false
+    }
+
+    // Good behaviour slowly earns back trust
+    pub fn reward(&mut self, peer: EndpointId) {
+        if let Some(penalty) = self.penalties.get_mut(&peer) {
+            *penalty = penalty.saturating_sub(Offence::BadMessage.penalty());
+        }
+    }
+
+    pub fn filter(&self, peers: Vec<EndpointId>) -> Vec<EndpointId> {
+        peers.into_iter().filter(|p| !self.is_banned(p)).collect()
+    }
+
+    pub fn encode_bans(&self) -> Result<Bytes> {
+        Ok(postcard::to_stdvec(&self.banned)?.into())
+    }
+
+    pub fn load_bans(&mut self, bytes: &[u8]) -> Result<()> {
+        self.banned = postcard::from_bytes(bytes)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<EndpointId, (f64, Instant)>,
+}
+
+impl RateLimiter {
+    // Token bucket per peer, returns false if the message should be dropped
+    pub fn allow(&mut self, peer: EndpointId) -> bool {
+        let now = Instant::now();
+        let (tokens, last) = self.buckets.entry(peer).or_insert((RATE_BURST, now));
+
+        let refill = now.duration_since(*last).as_secs_f64() * RATE_PER_SEC;
+        *tokens = (*tokens + refill).min(RATE_BURST);
+        *last = now;
+
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    pub fn forget(&mut self, peer: &EndpointId) {
+        self.buckets.remove(peer);
+    }
+}
+
+// Peers we have been connected to on a topic, so we can rejoin it without a ticket
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerBook {
+    pub topic_id: TopicId,
+    last_seen: BTreeMap<EndpointId, DateTime<Utc>>,
+    #[serde(skip)]
+    live: BTreeSet<EndpointId>,
+}
+
+impl PeerBook {
+    pub fn new(topic_id: TopicId) -> Self {
+        Self {
+            topic_id,
+            last_seen: BTreeMap::new(),
+            live: BTreeSet::new(),
+        }
+    }
+
+    pub fn neighbor_up(&mut self, peer: EndpointId) {
+        self.live.insert(peer);
+        self.last_seen.insert(peer, Utc::now());
+
+        if self.last_seen.len() > MAX_KNOWN_PEERS && let Some(oldest) = self.by_recency().pop() {
+            self.last_seen.remove(&oldest);
+        }
+    }
+
+    pub fn neighbor_down(&mut self, peer: &EndpointId) {
+        if self.live.remove(peer) {
+            self.last_seen.insert(*peer, Utc::now());
+        }
+    }
+
+    pub fn forget(&mut self, peer: &EndpointId) {
+        self.live.remove(peer);
+        self.last_seen.remove(peer);
+    }
+
+    // Every known peer, most recently seen first
+    pub fn by_recency(&self) -> Vec<EndpointId> {
+        let mut peers: Vec<(EndpointId, DateTime<Utc>)> = self.last_seen.iter().map(|(p, t)| (*p, *t)).collect();
+        peers.sort_by_key(|(_, t)| std::cmp::Reverse(*t));
+        peers.into_iter().map(|(p, _)| p).collect()
+    }
+
+    pub fn live_peers(&self) -> Vec<EndpointId> {
+        self.live.iter().cloned().collect()
+    }
+
+    pub fn encode(&self) -> Result<Bytes> {
+        Ok(postcard::to_stdvec(&self)?.into())
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<PeerBook> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+}
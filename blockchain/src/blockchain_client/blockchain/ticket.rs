@@ -3,8 +3,11 @@ use std::collections::BTreeSet;
 use iroh::EndpointId;
 use iroh_gossip::TopicId;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use anyhow::Result;
 
+use crate::error::validation;
+
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Ticket {
@@ -18,6 +21,12 @@ impl Ticket {
         Self::new(topic_id)
     }
 
+    // The topic nodes meet on through DHT discovery when nobody hands them a ticket
+    pub fn well_known() -> Self {
+        let hash = Sha256::digest(b"sm64-crypto mainnet");
+        Self::new(TopicId::from_bytes(hash.into()))
+    }
+
     pub fn new(topic_id: TopicId) -> Self {
         Self {
             topic_id,
@@ -25,7 +34,7 @@ impl Ticket {
         }
     }
     pub fn deserialize(input: &str) -> Result<Self> {
-        <Self as iroh_tickets::Ticket>::deserialize(input).map_err(Into::into)
+        <Self as iroh_tickets::Ticket>::deserialize(input).map_err(|e| validation(format!("Invalid ticket: {}", e)))
     }
     pub fn serialize(&self) -> String {
         <Self as iroh_tickets::Ticket>::serialize(self)
@@ -0,0 +1,100 @@
+use futures_lite::future;
+use iroh_blobs::Hash;
+use n0_future::time::{self, Duration, Instant};
+use sm64_binds::{GamePad, RngConfig, SM64GameGenerator};
+use tokio::sync::watch;
+
+use super::blockchain::{Block, BlockChain, BlockHead, RejectReason, SubmitOutcome};
+use crate::CHAIN_CFG;
+use crate::error::{validation, Result};
+use crate::trace::Trace;
+use crate::verifier::GameVerifier;
+
+// The longest solution is max_solution_time frames at 30fps. Allow double that for loading and pauses
+const MINING_TIMEOUT: Duration = Duration::from_secs(2 * CHAIN_CFG.max_solution_time as u64 / 30);
+// How far below the head a session's parent can be and still have its block kept as a fork block
+pub const DEFAULT_MAX_FORK_DEPTH: u128 = 2;
+
+// One attempt at mining a block on top of a particular head.
+// It goes stale as soon as another block replaces that head, and expires at its deadline
+#[derive(Debug)]
+pub struct MiningSession<G: GameVerifier = SM64GameGenerator> {
+    bc: BlockChain<G>,
+    block: Block,
+    parent: BlockHead,
+    heads: watch::Receiver<BlockHead>,
+    deadline: Instant,
+    max_fork_depth: u128,
+}
+
+impl<G: GameVerifier> MiningSession<G> {
+    pub(super) async fn new(bc: BlockChain<G>, miner_name: String, max_fork_depth: u128) -> Result<Self> {
+        let (parent, heads) = bc.watch_head().await?;
+        let block = Block::new(parent.clone(), miner_name)?;
+        let deadline = Instant::now() + MINING_TIMEOUT;
+        Ok(Self { bc, block, parent, heads, deadline, max_fork_depth })
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.block.calc_seed()
+    }
+
+    pub fn rng_config(&self) -> RngConfig {
+        self.block.calc_rng_config()
+    }
+
+    pub fn parent_hash(&self) -> Hash {
+        self.parent.hash
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    // True once our chain has a different head to the one we're mining on.
+    // Finishing the run is still worth it while the parent is within max_fork_depth of the head
+    pub fn is_stale(&self) -> bool {
+        self.heads.borrow().hash != self.parent.hash
+    }
+
+    // True once the parent is too far below the head for the block to be kept even as a fork block
+    pub fn is_too_deep(&self) -> bool {
+        // An empty chain's head sits at u128::MAX, so shift everything up one to put it below genesis
+        let head = self.heads.borrow().height.wrapping_add(1);
+        head.saturating_sub(self.parent.height.wrapping_add(1)) > self.max_fork_depth
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    // Resolves when the session is no longer worth playing, because the parent went stale or the deadline passed
+    pub async fn cancelled(&self) {
+        let mut heads = self.heads.clone();
+        let parent = self.parent.hash;
+        let stale = async move {
+            // An error means the chain was dropped, which is as good as stale
+            let _ = heads.wait_for(|head| head.hash != parent).await;
+        };
+        let expired = time::sleep(self.deadline.saturating_duration_since(Instant::now()));
+        future::or(stale, expired).await
+    }
+
+    // Replays inputs with this session's seed and rng config without submitting them,
+    // e.g. to see whether a movie recorded elsewhere would be accepted. Blocks while it runs, like trace_block
+    pub fn trace(&self, solution: Vec<GamePad>) -> Result<Trace<G::Game>> {
+        Trace::replay(self.bc.verifier(), self.seed(), self.rng_config(), solution)
+    }
+
+    // Seals the block with the recorded inputs and hands it to the chain
+    pub async fn submit(mut self, solution: Vec<GamePad>) -> Result<SubmitOutcome> {
+        if self.is_expired() {
+            return Err(validation("Mining session has expired. Use start_mine()").into());
+        }
+        if solution.len() > CHAIN_CFG.max_solution_time {
+            return Ok(SubmitOutcome::Rejected(RejectReason::TooLong));
+        }
+        self.block.seal(solution)?;
+        Ok(self.bc.submit_mine(self.block, self.max_fork_depth).await?)
+    }
+}
@@ -2,12 +2,17 @@
 pub struct ChainConfig {
     pub max_name_length: usize,
     pub max_solution_time: usize,
+    pub max_block_bytes: u64,
 }
 impl ChainConfig {
     const fn default() -> Self {
+        let max_name_length = 64;
+        let max_solution_time = 10 * 60 * 30;
         Self {
-            max_name_length: 64,
-            max_solution_time: 10 * 60 * 30,
+            max_name_length,
+            max_solution_time,
+            // A postcard GamePad is at most 5 bytes, the rest of the block is well under 1KiB
+            max_block_bytes: (max_solution_time * 5 + max_name_length + 1024) as u64,
         }
     }
 }
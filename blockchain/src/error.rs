@@ -0,0 +1,90 @@
+use snafu::{IntoError, Snafu};
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Everything the client API can fail with. Inside the crate errors travel as anyhow::Error,
+// and these are recovered by downcasting at the API boundary
+#[derive(Debug, Snafu)]
+pub enum Error {
+    // A block broke the chain rules, whoever sent it is to blame
+    #[snafu(display("Invalid block: {reason}"))]
+    InvalidBlock { reason: &'static str },
+    // Data or input that doesn't make sense: names, solutions, tickets, archives, peer messages
+    #[snafu(display("{message}"))]
+    Validation { message: String },
+    // Talking to other nodes failed
+    #[snafu(display("{message}: {source}"))]
+    Network {
+        message: String,
+        #[snafu(source(from(anyhow::Error, Into::into)))]
+        source: BoxError,
+    },
+    // The blob store failed, or returned something we can't read
+    #[snafu(display("Storage error: {source}"))]
+    Storage {
+        #[snafu(source(from(anyhow::Error, Into::into)))]
+        source: BoxError,
+    },
+    // The node was set up in a way that can't work
+    #[snafu(display("{message}"))]
+    Config { message: String },
+    // The game broke while replaying, which says nothing about the inputs it was given
+    #[snafu(display("{message}: {source}"))]
+    Game {
+        message: String,
+        #[snafu(source(from(anyhow::Error, Into::into)))]
+        source: BoxError,
+    },
+}
+
+impl Error {
+    // A short, stable name for the variant, for callers that can't match on it (e.g. JS)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::InvalidBlock { .. } => "invalid_block",
+            Error::Validation { .. } => "validation",
+            Error::Network { .. } => "network",
+            Error::Storage { .. } => "storage",
+            Error::Config { .. } => "config",
+            Error::Game { .. } => "game",
+        }
+    }
+}
+
+// Validation, network, config and game failures are tagged where they happen, so whatever is left is the store's
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<Error>() {
+            Ok(err) => err,
+            Err(err) => StorageSnafu.into_error(err),
+        }
+    }
+}
+
+pub(crate) fn is_invalid_block(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<Error>(), Some(Error::InvalidBlock { .. }))
+}
+
+pub(crate) fn invalid_block(reason: &'static str) -> anyhow::Error {
+    InvalidBlockSnafu { reason }.build().into()
+}
+
+pub(crate) fn validation(message: impl Into<String>) -> anyhow::Error {
+    ValidationSnafu { message }.build().into()
+}
+
+pub(crate) fn config(message: impl Into<String>) -> anyhow::Error {
+    ConfigSnafu { message }.build().into()
+}
+
+// For map_err on anything that talks to the network
+pub(crate) fn network<E: Into<anyhow::Error>>(message: &'static str) -> impl FnOnce(E) -> anyhow::Error {
+    move |err| NetworkSnafu { message }.into_error(err.into()).into()
+}
+
+// For map_err on anything that runs the game
+pub(crate) fn game<E: Into<anyhow::Error>>(message: &'static str) -> impl FnOnce(E) -> anyhow::Error {
+    move |err| GameSnafu { message }.into_error(err.into()).into()
+}
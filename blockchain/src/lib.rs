@@ -1,7 +1,22 @@
 mod blockchain_client;
-pub use blockchain_client::{BlockChainClient, Block, GamePad};
+pub use blockchain_client::{BlockChainClient, Block, GamePad, ConnectionState, Discovery, GcStats, MiningSession, DEFAULT_MAX_FORK_DEPTH, RejectReason, Storage, SubmitOutcome};
+#[cfg(feature = "test-utils")]
+pub use blockchain_client::{LocalDht, LocalNet};
 
 mod config;
 pub use config::CHAIN_CFG;
 
+mod error;
+pub use error::{Error, Result};
+mod verifier;
+pub use verifier::{Game, GameVerifier};
+#[cfg(feature = "test-utils")]
+mod mock;
+#[cfg(feature = "test-utils")]
+pub use mock::{MockGame, MockVerifier};
+mod m64;
+pub use m64::M64;
+mod trace;
+pub use trace::{Trace, TraceFormat, TraceFrame};
+
 pub use sm64_binds::RngConfig;
@@ -0,0 +1,128 @@
+use chrono::Utc;
+use sm64_binds::GamePad;
+
+use crate::error::{validation, Result};
+use crate::Block;
+
+// Mupen64 movie (.m64) layout, version 3. Header fields are little endian, inputs start at INPUT_OFFSET
+// with 4 bytes per controller per sample: the buttons in the N64's own order (big endian), then stick x and y
+const SIGNATURE: &[u8; 4] = b"M64\x1a";
+const VERSION: u32 = 3;
+const INPUT_OFFSET: usize = 0x400;
+// SM64 reads the pad once a frame at 30fps, the VI runs at 60
+const VIS_PER_SAMPLE: u32 = 2;
+const VIS_PER_SECOND: u8 = 60;
+const START_FROM_POWER_ON: u16 = 2;
+const CONTROLLER_1_PRESENT: u32 = 1;
+// What Mupen64 checks the loaded ROM against, for a US SM64
+const ROM_NAME: &[u8] = b"SUPER MARIO 64";
+const ROM_CRC: u32 = 0x635a_2bff;
+const ROM_COUNTRY: u16 = 0x45;
+
+const AUTHOR_OFFSET: usize = 0x222;
+const AUTHOR_LEN: usize = 222;
+const DESCRIPTION_OFFSET: usize = 0x300;
+const DESCRIPTION_LEN: usize = 256;
+
+// A single player movie that starts from power on, which is how every block's run starts
+#[derive(Debug, Clone, Default)]
+pub struct M64 {
+    pub author: String,
+    pub description: String,
+    pub rerecords: u32,
+    pub inputs: Vec<GamePad>,
+}
+
+impl M64 {
+    pub fn new(inputs: Vec<GamePad>) -> Self {
+        M64 { inputs, ..Default::default() }
+    }
+
+    // A block's run, credited to its miner
+    pub fn from_block(block: &Block) -> Self {
+        M64 {
+            author: block.miner_name.clone(),
+            description: format!("SM64 blockchain block at height {}", block.block_height),
+            rerecords: 0,
+            inputs: block.solution.clone(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; INPUT_OFFSET];
+        let samples = self.inputs.len() as u32;
+        bytes[0x000..0x004].copy_from_slice(SIGNATURE);
+        bytes[0x004..0x008].copy_from_slice(&VERSION.to_le_bytes());
+        // Movies are identified by when they were recorded
+        bytes[0x008..0x00c].copy_from_slice(&(Utc::now().timestamp() as u32).to_le_bytes());
+        bytes[0x00c..0x010].copy_from_slice(&samples.saturating_mul(VIS_PER_SAMPLE).to_le_bytes());
+        bytes[0x010..0x014].copy_from_slice(&self.rerecords.to_le_bytes());
+        bytes[0x014] = VIS_PER_SECOND;
+        bytes[0x015] = 1;
+        bytes[0x018..0x01c].copy_from_slice(&samples.to_le_bytes());
+        bytes[0x01c..0x01e].copy_from_slice(&START_FROM_POWER_ON.to_le_bytes());
+        bytes[0x020..0x024].copy_from_slice(&CONTROLLER_1_PRESENT.to_le_bytes());
+        bytes[0x0c4..0x0c4 + ROM_NAME.len()].copy_from_slice(ROM_NAME);
+        bytes[0x0e4..0x0e8].copy_from_slice(&ROM_CRC.to_le_bytes());
+        bytes[0x0e8..0x0ea].copy_from_slice(&ROM_COUNTRY.to_le_bytes());
+        write_str(&mut bytes[AUTHOR_OFFSET..AUTHOR_OFFSET + AUTHOR_LEN], &self.author);
+        write_str(&mut bytes[DESCRIPTION_OFFSET..DESCRIPTION_OFFSET + DESCRIPTION_LEN], &self.description);
+
+        for pad in self.inputs.iter() {
+            bytes.extend_from_slice(&pad.button.to_be_bytes());
+            bytes.push(pad.stick_x as u8);
+            bytes.push(pad.stick_y as u8);
+        }
+        bytes
+    }
+
+    // Takes controller 1's inputs. Other controllers, the ROM and the start type aren't checked,
+    // so a movie for another setup decodes fine but won't replay the same
+    pub fn decode(bytes: &[u8]) -> Result<M64> {
+        if bytes.len() < INPUT_OFFSET || &bytes[0x000..0x004] != SIGNATURE {
+            return Err(validation("Not an .m64 movie").into());
+        }
+        if read_u32(bytes, 0x004) != VERSION {
+            return Err(validation("Only version 3 .m64 movies are supported").into());
+        }
+        let flags = read_u32(bytes, 0x020);
+        if flags & CONTROLLER_1_PRESENT == 0 {
+            return Err(validation("The movie has no inputs for controller 1").into());
+        }
+        // Each sample has an entry for every controller that's plugged in, in port order
+        let stride = 4 * (flags & 0xf).count_ones() as usize;
+        let samples = read_u32(bytes, 0x018) as usize;
+        let data = &bytes[INPUT_OFFSET..];
+        if data.len() / stride < samples {
+            return Err(validation("The movie is truncated").into());
+        }
+
+        let inputs = data.chunks_exact(stride).take(samples)
+            .map(|s| GamePad::new(u16::from_be_bytes([s[0], s[1]]), s[2] as i8, s[3] as i8))
+            .collect();
+        Ok(M64 {
+            author: read_str(&bytes[AUTHOR_OFFSET..AUTHOR_OFFSET + AUTHOR_LEN]),
+            description: read_str(&bytes[DESCRIPTION_OFFSET..DESCRIPTION_OFFSET + DESCRIPTION_LEN]),
+            rerecords: read_u32(bytes, 0x010),
+            inputs,
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+// Zero padded UTF-8, cut short on a character boundary if it doesn't fit
+fn write_str(field: &mut [u8], s: &str) {
+    let mut len = s.len().min(field.len());
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+fn read_str(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use sm64_binds::{GamePad, GameState, RngConfig};
+
+use crate::verifier::{Game, GameVerifier};
+
+// A stand-in game for tests, no ROM needed. It's won after frames_to_win frames, and every frame
+// the stick has to point where the seed says, so a solution only replays for the block it was made for
+#[derive(Debug, Clone, Copy)]
+pub struct MockVerifier {
+    pub frames_to_win: usize,
+}
+
+impl MockVerifier {
+    pub fn new(frames_to_win: usize) -> Self {
+        Self { frames_to_win }
+    }
+
+    // The winning inputs for a seed
+    pub fn solve(&self, seed: u32) -> Vec<GamePad> {
+        (0..self.frames_to_win).map(|frame| mock_pad(seed, frame)).collect()
+    }
+}
+
+impl Default for MockVerifier {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+fn mock_pad(seed: u32, frame: usize) -> GamePad {
+    let x = seed.wrapping_mul(31).wrapping_add(frame as u32);
+    GamePad::new(0, x as i8, (x >> 8) as i8)
+}
+
+#[derive(Debug)]
+pub struct MockGame {
+    seed: u32,
+    frame: usize,
+    frames_to_win: usize,
+}
+
+impl GameVerifier for MockVerifier {
+    type Game = MockGame;
+
+    fn create_game(&self) -> Result<MockGame> {
+        Ok(MockGame { seed: 0, frame: 0, frames_to_win: self.frames_to_win })
+    }
+}
+
+impl Game for MockGame {
+    fn set_rng_seed(&mut self, seed: u32) -> Result<()> {
+        self.seed = seed;
+        Ok(())
+    }
+
+    fn set_rng_config(&mut self, _cfg: RngConfig) -> Result<()> {
+        Ok(())
+    }
+
+    fn rng_pad(&mut self, pad: GamePad) -> Result<GamePad> {
+        let forced = mock_pad(self.seed, self.frame);
+        Ok(GamePad::new(pad.button, forced.stick_x, forced.stick_y))
+    }
+
+    fn step(&mut self, _pad: GamePad) -> Result<()> {
+        self.frame += 1;
+        Ok(())
+    }
+
+    // Mario runs along x one unit a frame and gets his star on the last one
+    fn state(&mut self) -> Result<GameState> {
+        Ok(GameState {
+            num_stars: (self.frame >= self.frames_to_win) as i32,
+            pos: [self.frame as f32, 0.0, 0.0],
+            vel: [1.0, 0.0, 0.0],
+            lakitu_pos: [0.0; 3],
+            lakitu_yaw: 0,
+            in_credits: 0,
+            course_num: 0,
+            act_num: 0,
+            area_index: 0,
+        })
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use serde::Serialize;
+use sm64_binds::{GamePad, RngConfig};
+
+use crate::error::{game, validation, Error, Result};
+use crate::verifier::{Game, GameVerifier};
+use crate::Block;
+
+const CSV_HEADER: &str = "frame,button,stick_x,stick_y,rng_button,rng_stick_x,rng_stick_y,matches,\
+    pos_x,pos_y,pos_z,vel_x,vel_y,vel_z,course_num,act_num,area_index,num_stars,won";
+
+// What happened on one frame of a block's replay. The game doesn't report Mario's action (jumping, diving...),
+// so it isn't here. act_num is the course's act, the star being played for
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceFrame {
+    pub frame: usize,
+    // What the miner pressed
+    pub button: u16,
+    pub stick_x: i8,
+    pub stick_y: i8,
+    // What the block's rng would let them press. The replay fails on the first frame these don't match
+    pub rng_button: u16,
+    pub rng_stick_x: i8,
+    pub rng_stick_y: i8,
+    pub matches: bool,
+    // Mario after the frame
+    pub pos: [f32; 3],
+    pub vel: [f32; 3],
+    pub course_num: i32,
+    pub act_num: i32,
+    pub area_index: i32,
+    pub num_stars: i32,
+    pub won: bool,
+}
+
+impl TraceFrame {
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.frame, self.button, self.stick_x, self.stick_y,
+            self.rng_button, self.rng_stick_x, self.rng_stick_y, self.matches,
+            self.pos[0], self.pos[1], self.pos[2], self.vel[0], self.vel[1], self.vel[2],
+            self.course_num, self.act_num, self.area_index, self.num_stars, self.won,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    // One JSON object per frame
+    #[default]
+    Jsonl,
+    // A header row, then one row per frame
+    Csv,
+}
+
+impl FromStr for TraceFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jsonl" => Ok(TraceFormat::Jsonl),
+            "csv" => Ok(TraceFormat::Csv),
+            _ => Err(validation("Trace format should be jsonl or csv").into()),
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceFormat::Jsonl => write!(f, "jsonl"),
+            TraceFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+// Replays a block's solution one frame at a time, like validation does, but carries on past a frame
+// the rng doesn't allow so you can see where the run went. Ends after the winning frame or the last input
+pub struct Trace<Gm: Game> {
+    game: Gm,
+    solution: std::vec::IntoIter<GamePad>,
+    frame: usize,
+    done: bool,
+}
+
+impl<Gm: Game> Trace<Gm> {
+    pub(crate) fn new<G: GameVerifier<Game = Gm>>(verifier: &G, block: &Block) -> Result<Self> {
+        Self::replay(verifier, block.calc_seed(), block.calc_rng_config(), block.solution.clone())
+    }
+
+    pub(crate) fn replay<G: GameVerifier<Game = Gm>>(
+        verifier: &G, seed: u32, rng_config: RngConfig, solution: Vec<GamePad>
+    ) -> Result<Self> {
+        let start = || -> anyhow::Result<Gm> {
+            let mut game = verifier.create_game()?;
+            game.set_rng_seed(seed)?;
+            game.set_rng_config(rng_config)?;
+            Ok(game)
+        };
+        let game = start().map_err(game("Couldn't start the game"))?;
+        Ok(Trace { game, solution: solution.into_iter(), frame: 0, done: false })
+    }
+
+    fn step(&mut self, pad: GamePad) -> anyhow::Result<TraceFrame> {
+        let rng_pad = self.game.rng_pad(pad)?;
+        self.game.step(pad)?;
+        let state = self.game.state()?;
+        Ok(TraceFrame {
+            frame: self.frame,
+            button: pad.button,
+            stick_x: pad.stick_x,
+            stick_y: pad.stick_y,
+            rng_button: rng_pad.button,
+            rng_stick_x: rng_pad.stick_x,
+            rng_stick_y: rng_pad.stick_y,
+            matches: pad.equals(&rng_pad),
+            pos: state.pos,
+            vel: state.vel,
+            course_num: state.course_num,
+            act_num: state.act_num,
+            area_index: state.area_index,
+            num_stars: state.num_stars,
+            won: state.has_won(),
+        })
+    }
+
+    // Writes every remaining frame, returns how many were written
+    pub fn write(self, format: TraceFormat, mut writer: impl Write) -> Result<u64> {
+        if format == TraceFormat::Csv {
+            writeln!(writer, "{}", CSV_HEADER).map_err(write_error)?;
+        }
+        let mut count = 0;
+        for frame in self {
+            let frame = frame?;
+            let line = match format {
+                TraceFormat::Jsonl => serde_json::to_string(&frame).map_err(write_error)?,
+                TraceFormat::Csv => frame.csv_row(),
+            };
+            writeln!(writer, "{}", line).map_err(write_error)?;
+            count += 1;
+        }
+        writer.flush().map_err(write_error)?;
+        Ok(count)
+    }
+}
+
+// The writer is the caller's, so failing to write to it isn't a storage error
+fn write_error(err: impl fmt::Display) -> Error {
+    validation(format!("Couldn't write the trace: {}", err)).into()
+}
+
+impl<Gm: Game> Iterator for Trace<Gm> {
+    type Item = Result<TraceFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let pad = self.solution.next()?;
+        match self.step(pad) {
+            Ok(frame) => {
+                self.done = frame.won;
+                self.frame += 1;
+                Some(Ok(frame))
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(game("The game failed while replaying a block")(e).into()))
+            },
+        }
+    }
+}
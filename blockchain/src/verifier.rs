@@ -0,0 +1,56 @@
+use std::fmt::Debug;
+
+use anyhow::Result;
+use sm64_binds::{GamePad, GameState, RngConfig, SM64Game, SM64GameGenerator};
+
+// Builds the games that blocks are replayed in. The chain only needs these few calls,
+// so anything that can replay inputs deterministically can stand in for the real game
+pub trait GameVerifier: Debug + Clone + Send + Sync + 'static {
+    type Game: Game;
+
+    fn create_game(&self) -> Result<Self::Game>;
+}
+
+// One replay of a block's solution
+pub trait Game {
+    fn set_rng_seed(&mut self, seed: u32) -> Result<()>;
+    fn set_rng_config(&mut self, cfg: RngConfig) -> Result<()>;
+    // The pad the game will accept this frame, given the one the player pressed
+    fn rng_pad(&mut self, pad: GamePad) -> Result<GamePad>;
+    fn step(&mut self, pad: GamePad) -> Result<()>;
+    fn state(&mut self) -> Result<GameState>;
+
+    fn has_won(&mut self) -> Result<bool> {
+        Ok(self.state()?.has_won())
+    }
+}
+
+impl GameVerifier for SM64GameGenerator {
+    type Game = SM64Game;
+
+    fn create_game(&self) -> Result<SM64Game> {
+        SM64GameGenerator::create_game(self)
+    }
+}
+
+impl Game for SM64Game {
+    fn set_rng_seed(&mut self, seed: u32) -> Result<()> {
+        SM64Game::set_rng_seed(self, seed)
+    }
+
+    fn set_rng_config(&mut self, cfg: RngConfig) -> Result<()> {
+        SM64Game::set_rng_config(self, cfg)
+    }
+
+    fn rng_pad(&mut self, pad: GamePad) -> Result<GamePad> {
+        SM64Game::rng_pad(self, pad)
+    }
+
+    fn step(&mut self, pad: GamePad) -> Result<()> {
+        self.step_game(pad)
+    }
+
+    fn state(&mut self) -> Result<GameState> {
+        self.get_game_state()
+    }
+}
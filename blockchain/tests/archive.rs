@@ -0,0 +1,96 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::Sim;
+use iroh_blobs::Hash;
+use iroh_gossip::TopicId;
+use serde::Serialize;
+use sm64_blockchain::SubmitOutcome;
+
+// The archive's manifest, for writing ones the node would never make
+#[derive(Serialize)]
+struct Manifest {
+    version: u16,
+    chain_id: TopicId,
+    first_height: u128,
+    last_height: u128,
+    count: u64,
+    last_hash: Hash,
+    created: DateTime<Utc>,
+}
+
+fn archive_with(manifest: &Manifest) -> Vec<u8> {
+    let manifest = postcard::to_stdvec(manifest).unwrap();
+    let mut archive = b"SM64CHN\0".to_vec();
+    archive.extend_from_slice(&(manifest.len() as u32).to_be_bytes());
+    archive.extend_from_slice(&manifest);
+    archive
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exported_chain_imports_on_another_network() {
+    let from = Sim::new(2).await;
+    for _ in 0..4 {
+        assert_eq!(from.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    }
+    let (mut first, mut second) = (Vec::new(), Vec::new());
+    assert_eq!(from.nodes[0].export_chain(Some(0..=1), &mut first).await.unwrap(), 2);
+    // The end is clamped to the head
+    assert_eq!(from.nodes[0].export_chain(Some(2..=100), &mut second).await.unwrap(), 2);
+
+    // A separate network on its own chain, which has never heard of these blocks
+    let to = Sim::new(2).await;
+    assert_eq!(to.nodes[0].import_chain(first.as_slice()).await.unwrap(), 2);
+    assert_eq!(to.height(0).await, Some(1));
+    // The second part connects to the blocks the first one imported
+    assert_eq!(to.nodes[0].import_chain(second.as_slice()).await.unwrap(), 2);
+    assert_eq!(to.head(0).await, from.head(0).await);
+    for height in 0..4 {
+        assert_eq!(to.miner_at(0, height).await.as_deref(), Some("node0"));
+    }
+
+    // And the new head is announced like any other
+    assert_eq!(to.wait_converged().await, from.head(0).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn manifest_heights_that_overflow_are_rejected() {
+    let sim = Sim::new(2).await;
+    let mut manifest = Manifest {
+        version: 1,
+        chain_id: TopicId::from_bytes([0; 32]),
+        first_height: u128::MAX,
+        last_height: 0,
+        count: 2,
+        last_hash: Hash::EMPTY,
+        created: Utc::now(),
+    };
+    let err = sim.nodes[0].import_chain(archive_with(&manifest).as_slice()).await.unwrap_err();
+    assert_eq!(err.to_string(), "Archive manifest is inconsistent");
+
+    manifest.count = 0;
+    let err = sim.nodes[0].import_chain(archive_with(&manifest).as_slice()).await.unwrap_err();
+    assert_eq!(err.to_string(), "Archive manifest is inconsistent");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn broken_archives_are_rejected_as_bad_input() {
+    let sim = Sim::new(2).await;
+    for _ in 0..3 {
+        assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    }
+    let mut archive = Vec::new();
+    assert_eq!(sim.nodes[0].export_chain(None, &mut archive).await.unwrap(), 3);
+
+    // Cut off part way through the last block
+    let err = sim.nodes[1].import_chain(&archive[..archive.len() - 10]).await.unwrap_err();
+    assert_eq!(err.kind(), "validation");
+    assert_eq!(err.to_string(), "Archive is truncated or corrupt");
+
+    // The manifest's first byte is its version
+    let mut bad_manifest = archive.clone();
+    bad_manifest[12] = 0xff;
+    assert_eq!(sim.nodes[1].import_chain(bad_manifest.as_slice()).await.unwrap_err().kind(), "validation");
+
+    assert_eq!(sim.nodes[1].import_chain(&b"SM64"[..]).await.unwrap_err().kind(), "validation");
+}
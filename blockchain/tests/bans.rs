@@ -0,0 +1,48 @@
+mod common;
+
+use bytes::Bytes;
+use futures_lite::StreamExt;
+use iroh::protocol::Router;
+use iroh_gossip::{api::Event, net::Gossip};
+
+use common::{wait_until, Sim, TIMEOUT};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_banned_peer_is_dropped_and_refused() {
+    let sim = Sim::alone().await;
+    let node = &sim.nodes[0];
+
+    // A peer that speaks gossip but not the chain protocol
+    let endpoint = sim.raw_endpoint().await;
+    let gossip = Gossip::builder().spawn(endpoint.clone());
+    let _router = Router::builder(endpoint.clone()).accept(iroh_gossip::ALPN, gossip.clone()).spawn();
+    let (sender, mut receiver) = gossip.subscribe(node.topic_id(), vec![node.endpoint_id()]).await.unwrap().split();
+    tokio::time::timeout(TIMEOUT, receiver.joined()).await.expect("never joined").unwrap();
+    wait_until("the node to see the peer", || async { node.live_peers().await.contains(&endpoint.id()) }).await;
+
+    // Every undecodable message is a penalty, enough of them get the peer banned
+    for i in 0..5 {
+        // Gossip drops repeats, so each message has to be different
+        sender.broadcast_neighbors(Bytes::from(format!("garbage {i}"))).await.unwrap();
+    }
+    let dropped = async {
+        while let Some(event) = receiver.next().await {
+            if let Ok(Event::NeighborDown(peer)) = event
+                && peer == node.endpoint_id() {
+                return;
+            }
+        }
+    };
+    tokio::time::timeout(TIMEOUT, dropped).await.expect("the node kept the banned peer as a neighbour");
+    assert!(!node.live_peers().await.contains(&endpoint.id()));
+
+    // It can't come back, or download blocks from the node
+    for alpn in [iroh_gossip::ALPN, iroh_blobs::ALPN] {
+        // Depending on timing the node refuses during the handshake or right after it
+        let refusal = match endpoint.connect(node.endpoint_id(), alpn).await {
+            Ok(conn) => tokio::time::timeout(TIMEOUT, conn.closed()).await.expect("never closed").to_string(),
+            Err(e) => format!("{e:?}"),
+        };
+        assert!(refusal.contains("not allowed"), "unexpected close: {refusal}");
+    }
+}
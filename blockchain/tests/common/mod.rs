@@ -0,0 +1,144 @@
+// Runs a handful of nodes in one process on a LocalNet, replaying blocks with the mock game
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::time::Duration;
+
+use iroh::{Endpoint, SecretKey};
+use sm64_blockchain::{BlockChainClient, ConnectionState, GamePad, LocalNet, MockVerifier, Storage, SubmitOutcome};
+
+// Long enough for a download and a replay on a loaded CI machine, short enough to fail fast
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+const POLL: Duration = Duration::from_millis(50);
+
+pub struct Sim {
+    pub net: LocalNet,
+    pub nodes: Vec<BlockChainClient<MockVerifier>>,
+    verifier: MockVerifier,
+}
+
+impl Sim {
+    // n nodes on one new chain, all connected before this returns
+    pub async fn new(n: usize) -> Sim {
+        let mut sim = Sim { net: LocalNet::new(), nodes: Vec::new(), verifier: MockVerifier::default() };
+        for _ in 0..n {
+            sim.add_node().await;
+        }
+        sim.wait_connected().await;
+        sim
+    }
+
+    // One node on its own chain, for tests that play its peers by hand. A lone node never counts as connected
+    pub async fn alone() -> Sim {
+        let mut sim = Sim { net: LocalNet::new(), nodes: Vec::new(), verifier: MockVerifier::default() };
+        sim.add_node().await;
+        sim
+    }
+
+    // An endpoint on the net that isn't a node, for a test to speak whichever protocols it likes on
+    pub async fn raw_endpoint(&self) -> Endpoint {
+        self.net.bind(SecretKey::from_bytes(&rand::random())).await.expect("couldn't bind an endpoint")
+    }
+
+    // Joins a new node to the chain through the first node's ticket, returns its index
+    pub async fn add_node(&mut self) -> usize {
+        let ticket = match self.nodes.first() {
+            Some(first) => Some(first.get_ticket_with_peers(8).await),
+            None => None,
+        };
+        let name = format!("node{}", self.nodes.len());
+        let node = BlockChainClient::on_local_net(
+            self.net.clone(), self.verifier, name, ticket, Storage::Memory, None
+        ).await.expect("node failed to start");
+        self.nodes.push(node);
+        // Waiting here means the next node's ticket lists this one, so every node is a neighbour of every other.
+        // A partition drops the messages nodes would pass on, so nodes on one side have to be neighbours to sync
+        self.wait_meshed().await;
+        self.nodes.len() - 1
+    }
+
+    pub async fn wait_meshed(&self) {
+        wait_until("nodes to mesh", || async {
+            for (i, node) in self.nodes.iter().enumerate() {
+                let live = node.live_peers().await;
+                let linked = self.nodes.iter().enumerate()
+                    .all(|(j, other)| i == j || live.contains(&other.endpoint_id()));
+                if !linked {
+                    return false;
+                }
+            }
+            true
+        }).await;
+    }
+
+    pub async fn wait_connected(&self) {
+        for node in &self.nodes {
+            wait_until("nodes to connect", || async {
+                node.connection_state().await == ConnectionState::Connected
+            }).await;
+        }
+    }
+
+    // Mines one block on top of whatever node i thinks the head is
+    pub async fn mine(&self, i: usize) -> SubmitOutcome {
+        let session = self.nodes[i].start_mine().await.expect("couldn't start mining");
+        let solution = self.solve(session.seed());
+        session.submit(solution).await.expect("submit failed")
+    }
+
+    // The inputs that win the mock game for a seed
+    pub fn solve(&self, seed: u32) -> Vec<GamePad> {
+        self.verifier.solve(seed)
+    }
+
+    pub async fn head(&self, i: usize) -> String {
+        self.nodes[i].get_head_hash().await.expect("no head")
+    }
+
+    // The height of node i's head, None for an empty chain
+    pub async fn height(&self, i: usize) -> Option<u128> {
+        let block = self.nodes[i].get_block_from_str(self.head(i).await).await.ok()?;
+        Some(block.block_height)
+    }
+
+    pub async fn miner_at(&self, i: usize, height: u128) -> Option<String> {
+        let block = self.nodes[i].get_block_by_height(height).await.expect("lookup failed")?;
+        Some(block.miner_name)
+    }
+
+    // Waits for every listed node to have the same head as the first one, and returns it
+    pub async fn wait_agree(&self, nodes: &[usize]) -> String {
+        wait_until("heads to agree", || async {
+            let first = self.head(nodes[0]).await;
+            for i in &nodes[1..] {
+                if self.head(*i).await != first {
+                    return false;
+                }
+            }
+            true
+        }).await;
+        self.head(nodes[0]).await
+    }
+
+    pub async fn wait_converged(&self) -> String {
+        let all: Vec<usize> = (0..self.nodes.len()).collect();
+        self.wait_agree(&all).await
+    }
+
+    pub async fn wait_for_head(&self, i: usize, head: &str) {
+        wait_until("node to reach the head", || async { self.head(i).await == head }).await;
+    }
+}
+
+pub async fn wait_until<F, Fut>(what: &str, mut check: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let waited = tokio::time::timeout(TIMEOUT, async {
+        while !check().await {
+            tokio::time::sleep(POLL).await;
+        }
+    }).await;
+    assert!(waited.is_ok(), "timed out waiting for {}", what);
+}
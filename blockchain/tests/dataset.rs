@@ -0,0 +1,86 @@
+#![cfg(feature = "dataset")]
+
+mod common;
+
+use std::fs::File;
+use std::path::Path;
+
+use arrow_array::{Array, BooleanArray, ListArray, UInt64Array};
+use arrow_ipc::reader::FileReader;
+use common::Sim;
+use sm64_blockchain::{MockVerifier, SubmitOutcome};
+
+// Every row in the dataset, as (height, won, number of inputs), in file order
+fn read_episodes(dir: &Path) -> Vec<(u64, bool, usize)> {
+    let mut files: Vec<_> = std::fs::read_dir(dir).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "arrow"))
+        .collect();
+    files.sort();
+
+    let mut episodes = Vec::new();
+    for file in files {
+        for batch in FileReader::try_new(File::open(file).unwrap(), None).unwrap() {
+            let batch = batch.unwrap();
+            let height = batch.column_by_name("height").unwrap().as_any().downcast_ref::<UInt64Array>().unwrap();
+            let won = batch.column_by_name("won").unwrap().as_any().downcast_ref::<BooleanArray>().unwrap();
+            let button = batch.column_by_name("button").unwrap().as_any().downcast_ref::<ListArray>().unwrap();
+            for row in 0..batch.num_rows() {
+                episodes.push((height.value(row), won.value(row), button.value(row).len()));
+            }
+        }
+    }
+    episodes
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dataset_export_picks_up_where_it_left_off() {
+    let sim = Sim::new(2).await;
+    let dir = tempfile::tempdir().unwrap();
+    for _ in 0..3 {
+        assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    }
+
+    assert_eq!(sim.nodes[0].export_dataset(dir.path(), true, 2).await.unwrap(), 3);
+    assert_eq!(sim.nodes[0].export_dataset(dir.path(), true, 2).await.unwrap(), 0);
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    assert_eq!(sim.nodes[0].export_dataset(dir.path(), true, 2).await.unwrap(), 1);
+
+    let frames = MockVerifier::default().frames_to_win;
+    assert_eq!(read_episodes(dir.path()), (0..4).map(|h| (h, true, frames)).collect::<Vec<_>>());
+
+    // Every file in a dataset has the same columns
+    assert_eq!(sim.nodes[0].export_dataset(dir.path(), false, 2).await.unwrap_err().kind(), "validation");
+
+    // The directory is the caller's, so not being able to use it isn't a storage error
+    let not_a_dir = dir.path().join("manifest.json");
+    assert_eq!(sim.nodes[0].export_dataset(&not_a_dir, true, 2).await.unwrap_err().kind(), "validation");
+    std::fs::write(&not_a_dir, b"{").unwrap();
+    assert_eq!(sim.nodes[0].export_dataset(dir.path(), true, 2).await.unwrap_err().kind(), "validation");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dataset_export_replaces_blocks_lost_in_a_reorg() {
+    let sim = Sim::new(2).await;
+    let dir = tempfile::tempdir().unwrap();
+    for _ in 0..3 {
+        assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    }
+    sim.wait_converged().await;
+
+    sim.net.isolate(&[sim.nodes[1].endpoint_id()]);
+    assert_eq!(sim.mine(1).await, SubmitOutcome::AcceptedAsHead);
+    assert_eq!(sim.nodes[1].export_dataset(dir.path(), false, 2).await.unwrap(), 4);
+
+    // node0's longer fork replaces node1's block at height 3
+    sim.net.heal();
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+
+    // The file holding heights 2 and 3 is written again, with the rest of the new fork
+    assert_eq!(sim.nodes[1].export_dataset(dir.path(), false, 2).await.unwrap(), 3);
+    let heights: Vec<u64> = read_episodes(dir.path()).into_iter().map(|(h, _, _)| h).collect();
+    assert_eq!(heights, vec![0, 1, 2, 3, 4]);
+    assert_eq!(sim.miner_at(1, 3).await.as_deref(), Some("node0"));
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use common::wait_until;
+use sm64_blockchain::{BlockChainClient, ConnectionState, Discovery, LocalDht, LocalNet, MockVerifier, Storage, SubmitOutcome};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nodes_without_a_ticket_find_each_other() {
+    let net = LocalNet::new();
+    let dht = Discovery::Local(LocalDht::new());
+    let verifier = MockVerifier::default();
+    let mut nodes = Vec::new();
+    for name in ["node0", "node1"] {
+        let node = BlockChainClient::on_local_net(
+            net.clone(), verifier, name.into(), None, Storage::Memory, Some(dht.clone())
+        ).await.unwrap();
+        nodes.push(node);
+    }
+    // Neither has a ticket, so they only meet if the DHT puts them on the well-known topic together
+    for node in nodes.iter() {
+        wait_until("nodes to connect", || async { node.connection_state().await == ConnectionState::Connected }).await;
+    }
+
+    let session = nodes[0].start_mine().await.unwrap();
+    let solution = verifier.solve(session.seed());
+    assert_eq!(session.submit(solution).await.unwrap(), SubmitOutcome::AcceptedAsHead);
+    let head = nodes[0].get_head_hash().await.unwrap();
+    wait_until("the block to spread", || async { nodes[1].get_head_hash().await.unwrap() == head }).await;
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use iroh::protocol::Router;
+use iroh_blobs::{store::mem::MemStore, BlobsProtocol};
+
+use common::{wait_until, Sim};
+use sm64_blockchain::CHAIN_CFG;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn an_oversized_block_is_rejected_and_swept() {
+    let sim = Sim::alone().await;
+    let node = &sim.nodes[0];
+
+    // A peer serving a blob far past the block size limit
+    let endpoint = sim.raw_endpoint().await;
+    let store = MemStore::new();
+    let _router = Router::builder(endpoint.clone())
+        .accept(iroh_blobs::ALPN, BlobsProtocol::new(&store, None))
+        .spawn();
+    let oversized = vec![7u8; 10 * CHAIN_CFG.max_block_bytes as usize];
+    let hash = store.add_bytes(oversized).await.unwrap().hash.to_hex();
+
+    let err = node.fetch_block(hash.clone(), vec![endpoint.id()]).await.unwrap_err();
+    assert_eq!(err.kind(), "invalid_block");
+    // Whatever the node stored before giving up goes in the next sweep
+    wait_until("the sweep", || async { !node.holds_blob(hash.clone()).await.unwrap() }).await;
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{wait_until, Sim};
+use sm64_blockchain::SubmitOutcome;
+
+// Node 1 mines a block while cut off, then loses it to node 0's longer chain. Returns the lost block's hash
+async fn lose_a_fork(sim: &Sim) -> String {
+    sim.net.isolate(&[sim.nodes[1].endpoint_id()]);
+    assert_eq!(sim.mine(1).await, SubmitOutcome::AcceptedAsHead);
+    let fork = sim.head(1).await;
+    sim.net.heal();
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+    fork
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn garbage_collection_keeps_the_chain_and_recent_forks() {
+    let sim = Sim::new(2).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+    let old_fork = lose_a_fork(&sim).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+    let new_fork = lose_a_fork(&sim).await;
+    // Forks at heights 1 and 4, under a head at 5
+    assert_eq!(sim.height(1).await, Some(5));
+
+    let stats = sim.nodes[1].collect_garbage(2).await.unwrap();
+    // Old heads, journals and peer books go too
+    assert!(stats.deleted > 1);
+    assert_eq!(stats.kept_forks, 1);
+    let node = &sim.nodes[1];
+    wait_until("the sweep", || async { node.get_block_from_str(old_fork.clone()).await.is_err() }).await;
+
+    assert_eq!(node.get_block_from_str(new_fork).await.unwrap().block_height, 4);
+    assert_eq!(node.get_head_hash().await.unwrap(), sim.head(0).await);
+    let chain = node.get_blocks(0..=5).await.unwrap();
+    assert_eq!(chain.len(), 6);
+    assert!(chain.iter().all(|b| b.miner_name == "node0"));
+    assert_eq!(node.get_blocks_by_miner("node0").await.unwrap().len(), 6);
+    assert!(node.get_blocks_by_miner("node1").await.unwrap().is_empty());
+
+    // And it carries on mining
+    assert_eq!(sim.mine(1).await, SubmitOutcome::AcceptedAsHead);
+    assert_eq!(sim.wait_converged().await, sim.head(1).await);
+}
@@ -0,0 +1,54 @@
+// Legacy stores only ever lived on disk
+#![cfg(feature = "fs")]
+
+mod common;
+
+use common::{wait_until, Sim};
+use iroh_blobs::Hash;
+use iroh_blobs::store::fs::FsStore;
+use serde::Serialize;
+use sm64_blockchain::{BlockChainClient, LocalNet, MockVerifier, Storage, SubmitOutcome};
+
+// How the head is stored
+#[derive(Serialize)]
+struct BlockHead {
+    hash: Hash,
+    height: u128,
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chain_from_before_namespaced_tags_is_kept() {
+    let sim = Sim::new(2).await;
+    for _ in 0..3 {
+        assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    }
+    let blocks = sim.nodes[0].get_blocks(0..=2).await.unwrap();
+
+    // A store laid out the way older versions did it, with the chain's tags at the top level
+    let dir = tempfile::tempdir().unwrap();
+    let store = FsStore::load(dir.path()).await.unwrap();
+    let mut head = None;
+    for block in blocks.iter() {
+        let tag = store.add_bytes(block.encode().unwrap()).with_named_tag(block.block_height.to_string()).await.unwrap();
+        head = Some(BlockHead { hash: tag.hash, height: block.block_height });
+    }
+    store.add_bytes(postcard::to_stdvec(&head.unwrap()).unwrap()).with_named_tag("head").await.unwrap();
+    store.add_bytes(blocks[0].encode().unwrap()).with_named_tag("temp_7").await.unwrap();
+    // Something that really is garbage, so we can tell when the sweep has run
+    let mut orphan = blocks[0].clone();
+    orphan.miner_name = "orphan".into();
+    let orphan = store.add_bytes(orphan.encode().unwrap()).await.unwrap().hash;
+    store.shutdown().await.unwrap();
+
+    let node = BlockChainClient::on_local_net(
+        LocalNet::new(), MockVerifier::default(), "upgraded".into(), None, Storage::Path(dir.path().into()), None
+    ).await.unwrap();
+    assert_eq!(node.get_head_hash().await.unwrap(), sim.head(0).await);
+    assert_eq!(node.get_blocks(0..=2).await.unwrap().len(), 3);
+
+    // Nothing is left at the top level for garbage collection to mistake for its own
+    node.collect_garbage(0).await.unwrap();
+    wait_until("the sweep", || async { node.get_block_from_str(orphan.to_string()).await.is_err() }).await;
+    assert_eq!(node.get_head_hash().await.unwrap(), sim.head(0).await);
+    assert_eq!(node.get_block_by_height(0).await.unwrap().unwrap().miner_name, "node0");
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::Sim;
+use sm64_blockchain::{GamePad, M64, SubmitOutcome};
+
+#[test]
+fn movie_round_trips() {
+    let inputs = vec![GamePad::new(0x8000, 0, 0), GamePad::new(0x1020, -128, 127), GamePad::new(0, 5, -5)];
+    let movie = M64 { author: "node0".into(), description: "a test".into(), rerecords: 7, inputs: inputs.clone() };
+    let bytes = movie.encode();
+
+    assert_eq!(&bytes[..4], b"M64\x1a");
+    assert_eq!(bytes.len(), 0x400 + 4 * inputs.len());
+    // A is the top bit of the first byte, like on the N64
+    assert_eq!(&bytes[0x400..0x404], &[0x80, 0x00, 0x00, 0x00]);
+    assert_eq!(&bytes[0x404..0x408], &[0x10, 0x20, 0x80, 0x7f]);
+
+    let decoded = M64::decode(&bytes).unwrap();
+    assert_eq!(decoded.author, "node0");
+    assert_eq!(decoded.description, "a test");
+    assert_eq!(decoded.rerecords, 7);
+    assert!(decoded.inputs.iter().zip(inputs.iter()).all(|(a, b)| a.equals(b)));
+    assert_eq!(decoded.inputs.len(), inputs.len());
+}
+
+#[test]
+fn only_controller_1_is_read() {
+    let mut bytes = M64::new(vec![GamePad::new(0x4000, 1, 2)]).encode();
+    // Plug in controller 2 and give it its own sample
+    bytes[0x20] = 0b11;
+    bytes.extend_from_slice(&[0xff, 0xff, 9, 9]);
+
+    let decoded = M64::decode(&bytes).unwrap();
+    assert_eq!(decoded.inputs.len(), 1);
+    assert!(decoded.inputs[0].equals(&GamePad::new(0x4000, 1, 2)));
+}
+
+#[test]
+fn truncated_movie_is_rejected() {
+    let bytes = M64::new(vec![GamePad::default(); 4]).encode();
+    assert_eq!(M64::decode(&bytes[..bytes.len() - 1]).unwrap_err().kind(), "validation");
+    assert!(M64::decode(b"not a movie").is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn movie_of_a_block_checks_out_against_its_session() {
+    let sim = Sim::new(2).await;
+    let session = sim.nodes[0].start_mine().await.unwrap();
+    let movie = M64::decode(&M64::new(sim.solve(session.seed())).encode()).unwrap();
+
+    let frames: Vec<_> = session.trace(movie.inputs.clone()).unwrap().collect::<Result<_, _>>().unwrap();
+    assert!(frames.iter().all(|f| f.matches));
+    assert!(frames.last().unwrap().won);
+    assert_eq!(session.submit(movie.inputs).await.unwrap(), SubmitOutcome::AcceptedAsHead);
+
+    let block = sim.nodes[0].get_block_by_height(0).await.unwrap().unwrap();
+    let exported = M64::decode(&M64::from_block(&block).encode()).unwrap();
+    assert_eq!(exported.author, "node0");
+    assert_eq!(exported.inputs.len(), block.solution.len());
+}
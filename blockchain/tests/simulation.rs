@@ -0,0 +1,131 @@
+mod common;
+
+use std::time::Duration;
+
+use common::Sim;
+use sm64_blockchain::{RejectReason, SubmitOutcome};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mined_blocks_reach_every_node() {
+    let sim = Sim::new(3).await;
+    for i in 0..3 {
+        assert_eq!(sim.mine(i).await, SubmitOutcome::AcceptedAsHead);
+        let head = sim.head(i).await;
+        sim.wait_for_head((i + 1) % 3, &head).await;
+    }
+
+    sim.wait_converged().await;
+    for i in 0..3 {
+        assert_eq!(sim.height(i).await, Some(2));
+    }
+    assert_eq!(sim.miner_at(2, 0).await.as_deref(), Some("node0"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn late_joiner_syncs_the_whole_chain() {
+    let mut sim = Sim::new(2).await;
+    for _ in 0..4 {
+        assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    }
+    let head = sim.wait_converged().await;
+
+    let late = sim.add_node().await;
+    sim.wait_for_head(late, &head).await;
+    assert_eq!(sim.height(late).await, Some(3));
+    assert_eq!(sim.nodes[late].get_blocks(0..=3).await.unwrap().len(), 4);
+
+    // And it takes part like everyone else
+    assert_eq!(sim.mine(late).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+    assert_eq!(sim.height(0).await, Some(4));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn longer_side_of_a_partition_wins() {
+    let sim = Sim::new(4).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+
+    sim.net.isolate(&[sim.nodes[2].endpoint_id(), sim.nodes[3].endpoint_id()]);
+    for _ in 0..3 {
+        assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    }
+    assert_eq!(sim.mine(2).await, SubmitOutcome::AcceptedAsHead);
+    let long = sim.wait_agree(&[0, 1]).await;
+    let short = sim.wait_agree(&[2, 3]).await;
+    assert_ne!(long, short);
+    assert_eq!(sim.height(2).await, Some(1));
+
+    // The next block on the long side reorgs the short side onto it
+    sim.net.heal();
+    assert_eq!(sim.mine(1).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+    for i in 0..4 {
+        assert_eq!(sim.height(i).await, Some(4));
+        assert_eq!(sim.miner_at(i, 1).await.as_deref(), Some("node0"));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fork_at_the_same_height_resolves_on_the_next_block() {
+    let sim = Sim::new(2).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+
+    // With slow links both nodes find a block at height 1 before hearing about the other one
+    sim.net.set_latency(Duration::from_secs(1));
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    assert_eq!(sim.mine(1).await, SubmitOutcome::AcceptedAsHead);
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    // A head at the same height isn't better, so each keeps its own
+    assert_ne!(sim.head(0).await, sim.head(1).await);
+    assert_eq!(sim.miner_at(0, 1).await.as_deref(), Some("node0"));
+    assert_eq!(sim.miner_at(1, 1).await.as_deref(), Some("node1"));
+
+    sim.net.set_latency(Duration::ZERO);
+    assert_eq!(sim.mine(1).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+    assert_eq!(sim.height(0).await, Some(2));
+    assert_eq!(sim.miner_at(0, 1).await.as_deref(), Some("node1"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stale_session_is_kept_as_a_fork_block() {
+    let sim = Sim::new(2).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    sim.wait_converged().await;
+
+    let session = sim.nodes[1].start_mine().await.unwrap();
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    let head = sim.head(0).await;
+    sim.wait_for_head(1, &head).await;
+    assert!(session.is_stale());
+    assert!(!session.is_too_deep());
+
+    let solution = sim.solve(session.seed());
+    assert_eq!(session.submit(solution).await.unwrap(), SubmitOutcome::AcceptedAsSideChain);
+    assert_eq!(sim.head(1).await, head);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wrong_inputs_are_rejected() {
+    let sim = Sim::new(2).await;
+    let session = sim.nodes[0].start_mine().await.unwrap();
+    let solution = sim.solve(session.seed().wrapping_add(1));
+    assert_eq!(session.submit(solution).await.unwrap(), SubmitOutcome::Rejected(RejectReason::ReplayFailed));
+    assert_eq!(sim.height(0).await, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chain_converges_over_slow_links() {
+    let sim = Sim::new(3).await;
+    sim.net.set_latency(Duration::from_millis(200));
+    for round in 0..6 {
+        let miner = round % 3;
+        sim.mine(miner).await;
+        let head = sim.head(miner).await;
+        sim.wait_for_head((miner + 1) % 3, &head).await;
+    }
+    sim.wait_converged().await;
+    assert_eq!(sim.height(0).await, Some(5));
+}
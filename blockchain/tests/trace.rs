@@ -0,0 +1,82 @@
+mod common;
+
+use std::io::{self, Write};
+
+use common::Sim;
+use sm64_blockchain::{BlockChainClient, GameVerifier, MockGame, MockVerifier, Storage, SubmitOutcome, TraceFormat};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn trace_follows_a_block_to_the_winning_frame() {
+    let sim = Sim::new(2).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    let block = sim.nodes[0].get_block_by_height(0).await.unwrap().unwrap();
+
+    let frames: Vec<_> = sim.nodes[0].trace_block(&block).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(frames.len(), MockVerifier::default().frames_to_win);
+    assert!(frames.iter().all(|f| f.matches));
+    assert!(frames.last().unwrap().won);
+    assert!(!frames[0].won);
+
+    let mut csv = Vec::new();
+    let count = sim.nodes[0].export_trace(&block, TraceFormat::Csv, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count() as u64, count + 1);
+    assert!(csv.starts_with("frame,"));
+
+    let mut jsonl = Vec::new();
+    sim.nodes[0].export_trace(&block, TraceFormat::Jsonl, &mut jsonl).unwrap();
+    let last: serde_json::Value = serde_json::from_str(String::from_utf8(jsonl).unwrap().lines().last().unwrap()).unwrap();
+    assert_eq!(last["won"], true);
+
+    // A writer failing is the caller's problem, not the store's
+    assert_eq!(sim.nodes[0].export_trace(&block, TraceFormat::Csv, FullDisk).unwrap_err().kind(), "validation");
+}
+
+struct FullDisk;
+
+impl Write for FullDisk {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("disk full"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn trace_shows_where_a_bad_solution_goes_wrong() {
+    let sim = Sim::new(2).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    let mut block = sim.nodes[0].get_block_by_height(0).await.unwrap().unwrap();
+    block.solution[3].stick_x = block.solution[3].stick_x.wrapping_add(1);
+
+    let frames: Vec<_> = sim.nodes[0].trace_block(&block).unwrap().collect::<Result<_, _>>().unwrap();
+    let first_bad = frames.iter().position(|f| !f.matches);
+    assert_eq!(first_bad, Some(3));
+}
+
+// Stands in for a missing or broken ROM
+#[derive(Debug, Clone)]
+struct BrokenGame;
+
+impl GameVerifier for BrokenGame {
+    type Game = MockGame;
+
+    fn create_game(&self) -> anyhow::Result<MockGame> {
+        Err(anyhow::anyhow!("emulator crashed"))
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_broken_game_is_not_blamed_on_the_block_or_the_store() {
+    let sim = Sim::new(2).await;
+    assert_eq!(sim.mine(0).await, SubmitOutcome::AcceptedAsHead);
+    let block = sim.nodes[0].get_block_by_height(0).await.unwrap().unwrap();
+
+    let node = BlockChainClient::on_local_net(
+        sim.net.clone(), BrokenGame, "broken".into(), None, Storage::Memory, None
+    ).await.unwrap();
+    let err = node.export_trace(&block, TraceFormat::Csv, io::sink()).unwrap_err();
+    assert_eq!(err.kind(), "game");
+}
@@ -3,9 +3,12 @@ use anyhow::Result;
 
 use tracing::{level_filters::LevelFilter};
 use tracing_subscriber_wasm::MakeConsoleWriter;
-use wasm_bindgen::{JsError, prelude::wasm_bindgen};
+use n0_future::StreamExt;
+use wasm_bindgen::{JsValue, prelude::wasm_bindgen};
+use wasm_streams::{ReadableStream, readable::sys};
 use hex::ToHex;
-use sm64_blockchain::{BlockChainClient, GamePad, Block, RngConfig, CHAIN_CFG};
+use chrono::DateTime;
+use sm64_blockchain::{BlockChainClient, Error, GamePad, Block, MiningSession, RngConfig, Storage, CHAIN_CFG};
 
 #[wasm_bindgen(start)]
 fn start() {
@@ -61,6 +64,34 @@ impl RngAndSeedWeb {
     pub fn z_prob(&self) -> f32 {self.0.z_prob}
 }
 
+#[wasm_bindgen]
+pub struct MiningSessionWeb(MiningSession);
+#[wasm_bindgen]
+impl MiningSessionWeb {
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u32 {self.0.seed()}
+    pub fn rng_and_seed(&self) -> RngAndSeedWeb {
+        RngAndSeedWeb(self.0.rng_config(), self.0.seed())
+    }
+    #[wasm_bindgen(getter)]
+    pub fn parent_hash(&self) -> String {self.0.parent_hash().encode_hex()}
+    // True once another block has replaced the head we're mining on
+    pub fn is_stale(&self) -> bool {self.0.is_stale()}
+    // True once the parent is too far behind for the block to be kept even as a fork block
+    pub fn is_too_deep(&self) -> bool {self.0.is_too_deep()}
+    pub fn is_expired(&self) -> bool {self.0.is_expired()}
+    // Resolves when the parent goes stale or the session expires
+    pub async fn cancelled(&self) {
+        self.0.cancelled().await
+    }
+    // Uses up the session, it can't be submitted twice.
+    // Returns "head", "side_chain", "stale_parent", "replay_failed" or "too_long"
+    pub async fn submit(self, solution: Vec<GamePadWeb>) -> Result<String, JsValue> {
+        let solution = solution.into_iter().map(|pad| pad.0).collect();
+        let outcome = self.0.submit(solution).await.map_err(to_js_err)?;
+        Ok(outcome.as_str().to_string())
+    }
+}
 
 #[wasm_bindgen]
 pub struct BlockWeb(Block);
@@ -93,62 +124,97 @@ pub struct BlockChainClientWeb(BlockChainClient);
 
 #[wasm_bindgen]
 impl BlockChainClientWeb {
-    pub async fn new(rom_bytes: Vec<u8>, miner_name: String, ticket_str: String) -> Result<Self, JsError> {
+    pub async fn new(rom_bytes: Vec<u8>, miner_name: String, ticket_str: String) -> Result<Self, JsValue> {
         let ticket_opt = match ticket_str.len() == 0 {
             true => None,
             false => Some(ticket_str),
         };
 
-        let client = BlockChainClient::new(rom_bytes, miner_name, ticket_opt)
+        // Browsers have no filesystem to keep the key in, or UDP sockets for the DHT
+        let client = BlockChainClient::new(rom_bytes, miner_name, ticket_opt, Storage::Memory, false, None)
             .await
             .map_err(to_js_err)?;
 
         Ok(Self(client))
     }
 
-    pub fn get_ticket(&self) -> Result<String, JsError> {
+    // Follow another chain (e.g. a testnet) on the same node
+    pub async fn follow(&self, ticket_str: String) -> Result<BlockChainClientWeb, JsValue> {
+        let client = self.0.follow(ticket_str).await.map_err(to_js_err)?;
+        Ok(Self(client))
+    }
+
+    pub fn get_ticket(&self) -> Result<String, JsValue> {
         Ok(self.0.get_ticket())
     }
 
-    pub async fn start_mine(&mut self) -> Result<RngAndSeedWeb, JsError> {
-        let (seed, rng_config) = self.0.start_mine().await.map_err(to_js_err)?;
-        Ok(RngAndSeedWeb(rng_config, seed))
+    pub async fn get_ticket_with_peers(&self, max_peers: usize) -> Result<String, JsValue> {
+        Ok(self.0.get_ticket_with_peers(max_peers).await)
     }
 
-    pub async fn submit_mine(&mut self, seed: u32, solution: Vec<GamePadWeb>) -> Result<(), JsError> {
-        // let mut solution_pads: Vec<GamePad> = Vec::new();
-        // for chunk in solution.chunks(4) {
-        //     if chunk.len() == 4 {
-        //         // Only create GamePad if there's a complete chunk of 4 bytes
-        //         let pad = GamePad::from_bytes(chunk);
-        //         solution_pads.push(pad);
-        //     } else {
-        //         einfo!("Warning: Incomplete chunk ignored: {:?}", chunk);
-        //     }
-        // }
+    pub fn set_max_fork_depth(&mut self, depth: u128) {
+        self.0.set_max_fork_depth(depth)
+    }
 
-        let mut solution_pads: Vec<GamePad> = Vec::new();
-        for webpad in solution {
-            solution_pads.push(webpad.0);
-        }
+    pub async fn start_mine(&self) -> Result<MiningSessionWeb, JsValue> {
+        let session = self.0.start_mine().await.map_err(to_js_err)?;
+        Ok(MiningSessionWeb(session))
+    }
 
-        self.0.submit_mine(seed, solution_pads).await.map_err(to_js_err)
+    // "joining", "connected" or "disconnected"
+    pub async fn connection_state(&self) -> String {
+        self.0.connection_state().await.as_str().to_string()
     }
 
     pub async fn has_new_block(&self) -> bool {
         self.0.has_new_block().await
     }
 
-    pub async fn get_head_hash(&self) -> Result<String, JsError> {
+    pub async fn get_head_hash(&self) -> Result<String, JsValue> {
         let head_hash = self.0.get_head_hash().await.map_err(to_js_err)?;
         Ok(head_hash)
     }
 
-    pub async fn get_block(&self, hash_str: String) -> Result<BlockWeb, JsError> {
+    pub async fn get_block(&self, hash_str: String) -> Result<BlockWeb, JsValue> {
         let block = self.0.get_block_from_str(hash_str).await.map_err(to_js_err)?;
         Ok(BlockWeb(block))
     }
 
+    pub async fn get_block_by_height(&self, height: u128) -> Result<Option<BlockWeb>, JsValue> {
+        let block = self.0.get_block_by_height(height).await.map_err(to_js_err)?;
+        Ok(block.map(BlockWeb))
+    }
+
+    // Canonical blocks from `from` to `to` inclusive, fewer if the chain ends first
+    pub async fn get_blocks(&self, from: u128, to: u128) -> Result<Vec<BlockWeb>, JsValue> {
+        let blocks = self.0.get_blocks(from..=to).await.map_err(to_js_err)?;
+        Ok(blocks.into_iter().map(BlockWeb).collect())
+    }
+
+    pub async fn get_blocks_by_miner(&self, miner_name: String) -> Result<Vec<BlockWeb>, JsValue> {
+        let blocks = self.0.get_blocks_by_miner(&miner_name).await.map_err(to_js_err)?;
+        Ok(blocks.into_iter().map(BlockWeb).collect())
+    }
+
+    // Times are unix milliseconds, like Date.now()
+    pub async fn get_blocks_between(&self, from_ms: i64, to_ms: i64) -> Result<Vec<BlockWeb>, JsValue> {
+        let from = DateTime::from_timestamp_millis(from_ms)
+            .ok_or_else(|| to_js_err(Error::Validation { message: "Invalid start time".into() }))?;
+        let to = DateTime::from_timestamp_millis(to_ms)
+            .ok_or_else(|| to_js_err(Error::Validation { message: "Invalid end time".into() }))?;
+        let blocks = self.0.get_blocks_between(from, to).await.map_err(to_js_err)?;
+        Ok(blocks.into_iter().map(BlockWeb).collect())
+    }
+
+    // A ReadableStream of BlockWeb from height `from` up to the head, use with `for await`
+    pub fn blocks_from(&self, from: u128) -> sys::ReadableStream {
+        let stream = self.0.blocks_from(from).map(|block| match block {
+            Ok(block) => Ok(JsValue::from(BlockWeb(block))),
+            Err(e) => Err(to_js_err(e)),
+        });
+        ReadableStream::from_stream(stream).into_raw()
+    }
+
     pub fn get_max_name_length() -> usize {
         CHAIN_CFG.max_name_length
     }
@@ -158,7 +224,11 @@ impl BlockChainClientWeb {
 
 }
 
-fn to_js_err(err: impl Into<anyhow::Error>) -> JsError {
-    let err: anyhow::Error = err.into();
-    JsError::new(&err.to_string())
-}
\ No newline at end of file
+// Errors reach JS as Error objects named after the kind of failure ("invalid_block", "validation",
+// "network", "storage", "config" or "game"), so callers can branch on err.name instead of the message
+fn to_js_err(err: impl Into<Error>) -> JsValue {
+    let err: Error = err.into();
+    let js_err = js_sys::Error::new(&err.to_string());
+    js_err.set_name(err.kind());
+    js_err.into()
+}
@@ -1,7 +1,8 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use std::fs::File;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc};
 use tokio::time;
@@ -9,7 +10,7 @@ use tokio::time;
 use anyhow::Result;
 use tracing::info;
 
-use sm64_blockchain::BlockChainClient;
+use sm64_blockchain::{Block, BlockChainClient, Discovery, M64, Storage, TraceFormat};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -23,6 +24,77 @@ struct Args {
     // name: String,
     #[clap(short, long, default_value_t = String::from(""))]
     ticket: String,
+    /// Directory for the chain data, so several nodes can run from one directory [default: blockchain_data]
+    #[clap(long)]
+    data_dir: Option<PathBuf>,
+    /// Where to write our ticket, defaults to ticket.txt in --data-dir if one is given, else in the working directory
+    #[clap(long)]
+    ticket_file: Option<PathBuf>,
+    /// Keep everything in memory instead of on disk
+    #[clap(short, long, default_value_t = false)]
+    memory: bool,
+    /// Keep the same node identity across restarts, so old tickets keep working
+    #[clap(short, long, default_value_t = false)]
+    persist_key: bool,
+    /// How many of our live neighbours to add to the printed ticket as extra bootstrap peers
+    #[clap(long, default_value_t = 0)]
+    ticket_peers: usize,
+    /// Find peers through the mainline DHT, so no ticket is needed
+    #[clap(short, long, default_value_t = false)]
+    dht: bool,
+    /// Garbage collect the store on startup and every hour, keeping side-chain blocks this close to the head
+    #[clap(long)]
+    gc_window: Option<u128>,
+    /// Also follow the chain in this ticket, can be given several times
+    #[clap(short, long)]
+    follow: Vec<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the canonical chain (or the heights between --from and --to) to an archive file, then exit
+    Export {
+        path: PathBuf,
+        #[clap(long)]
+        from: Option<u128>,
+        #[clap(long)]
+        to: Option<u128>,
+    },
+    /// Replay every block in an archive file and adopt it if it beats our head, then exit
+    Import {
+        path: PathBuf,
+    },
+    /// Replay a block and write Mario's state on every frame to a file, then exit.
+    /// Mario's action isn't included, the game doesn't report it. act_num is the course's act
+    Trace {
+        /// A block hash, or a height on our canonical chain
+        block: String,
+        path: PathBuf,
+        /// jsonl or csv
+        #[clap(long, default_value_t = TraceFormat::Jsonl)]
+        format: TraceFormat,
+    },
+    /// Add the blocks mined since the last run to a gameplay dataset (Arrow IPC files, one row per block), then exit
+    Dataset {
+        dir: PathBuf,
+        /// Include Mario's state on every frame
+        #[clap(long, default_value_t = false)]
+        traces: bool,
+        #[clap(long, default_value_t = 1000)]
+        blocks_per_file: u64,
+    },
+    /// Write a block's inputs as a Mupen64 movie (.m64), then exit
+    ExportMovie {
+        /// A block hash, or a height on our canonical chain
+        block: String,
+        path: PathBuf,
+    },
+    /// Replay a Mupen64 movie (.m64) as if it was mined on our head and say whether it would be accepted, then exit
+    CheckMovie {
+        path: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -39,22 +111,121 @@ async fn main() -> Result<()>{
 
     let rom_bytes = include_bytes!("../../baserom.us.z64").to_vec();
     
-    let ticket_opt = match args.ticket.len() == 0 {
+    let ticket_opt = match args.ticket.is_empty() {
         true => None,
         false => Some(args.ticket),
     };
 
+    let discovery = match args.dht {
+        true => Some(Discovery::mainline()?),
+        false => None,
+    };
+
+    let storage = match args.memory {
+        true => Storage::Memory,
+        false => Storage::Path(args.data_dir.clone().unwrap_or_else(|| PathBuf::from("blockchain_data"))),
+    };
+
     let name = String::from("");
-    let bc_client = BlockChainClient::new(rom_bytes, name, ticket_opt).await.expect("Failed to create blockchain client");
+    let bc_client = BlockChainClient::new(rom_bytes, name, ticket_opt, storage, args.persist_key, discovery).await.expect("Failed to create blockchain client");
 
+    match args.command {
+        Some(Command::Export { path, from, to }) => {
+            let range = match (from, to) {
+                (None, None) => None,
+                (from, to) => Some(from.unwrap_or(0)..=to.unwrap_or(u128::MAX)),
+            };
+            let file = io::BufWriter::new(File::create(&path)?);
+            let count = bc_client.export_chain(range, file).await?;
+            info!("Exported {} blocks to {}", count, path.display());
+            return Ok(());
+        },
+        Some(Command::Import { path }) => {
+            let file = io::BufReader::new(File::open(&path)?);
+            let count = bc_client.import_chain(file).await?;
+            info!("Imported {} blocks from {}", count, path.display());
+            return Ok(());
+        },
+        Some(Command::Trace { block, path, format }) => {
+            let block = find_block(&bc_client, &block).await?;
+            let file = io::BufWriter::new(File::create(&path)?);
+            // The replay runs the emulator, so the runtime moves its other tasks off this thread meanwhile
+            let count = tokio::task::block_in_place(|| bc_client.export_trace(&block, format, file))?;
+            info!("Wrote {} frames of block {} to {}", count, block.block_height, path.display());
+            return Ok(());
+        },
+        Some(Command::ExportMovie { block, path }) => {
+            let block = find_block(&bc_client, &block).await?;
+            std::fs::write(&path, M64::from_block(&block).encode())?;
+            info!("Wrote {} frames of block {} to {}", block.solution.len(), block.block_height, path.display());
+            return Ok(());
+        },
+        Some(Command::CheckMovie { path }) => {
+            let movie = M64::decode(&std::fs::read(&path)?)?;
+            let session = bc_client.start_mine().await?;
+            let mut frames = 0;
+            let mut first_mismatch = None;
+            let mut won = false;
+            tokio::task::block_in_place(|| -> Result<()> {
+                for frame in session.trace(movie.inputs)? {
+                    let frame = frame?;
+                    if !frame.matches && first_mismatch.is_none() {
+                        first_mismatch = Some(frame.frame);
+                    }
+                    frames += 1;
+                    won = frame.won;
+                }
+                Ok(())
+            })?;
+            match (first_mismatch, won) {
+                (None, true) => info!("Accepted: the movie wins on frame {}", frames - 1),
+                (None, false) => info!("Rejected: the movie never wins"),
+                (Some(frame), _) => info!("Rejected: frame {} isn't allowed by the rng with seed {}", frame, session.seed()),
+            }
+            return Ok(());
+        },
+        Some(Command::Dataset { dir, traces, blocks_per_file }) => {
+            let count = bc_client.export_dataset(&dir, traces, blocks_per_file).await?;
+            info!("Added {} episodes to {}", count, dir.display());
+            return Ok(());
+        },
+        None => {},
+    }
 
+    // Only nodes with their own --data-dir keep their ticket there, so a single node still writes ./ticket.txt
+    let ticket_file = match (args.ticket_file, &args.data_dir) {
+        (Some(file), _) => file,
+        (None, Some(dir)) => dir.join("ticket.txt"),
+        (None, None) => PathBuf::from("ticket.txt"),
+    };
     let ticket_str = bc_client.get_ticket();
     info!("Join us at:\n{}\n", ticket_str);
-    write_ticket_to_file(&ticket_str)?;
+    write_ticket_to_file(&ticket_file, &ticket_str)?;
+
+    // Each followed chain syncs in the background on the same endpoint and store
+    for ticket in args.follow.iter() {
+        let client = bc_client.follow(ticket.clone()).await.expect("Failed to follow chain");
+        info!("Also following:\n{}\n", client.get_ticket());
+    }
 
+    let mut ticket_refresh = time::Instant::now();
+    let mut last_gc: Option<time::Instant> = None;
     loop {
         time::sleep(time::Duration::from_millis(10)).await;
 
+        if let Some(window) = args.gc_window
+            && last_gc.is_none_or(|t| t.elapsed() > time::Duration::from_secs(60 * 60)) {
+            last_gc = Some(time::Instant::now());
+            let stats = bc_client.collect_garbage(window).await?;
+            info!("Reclaimed {} bytes from {} blobs", stats.reclaimed_bytes, stats.deleted);
+        }
+
+        // Neighbours come and go, so keep the ticket file pointing at live ones
+        if args.ticket_peers > 0 && ticket_refresh.elapsed() > time::Duration::from_secs(30) {
+            ticket_refresh = time::Instant::now();
+            write_ticket_to_file(&ticket_file, &bc_client.get_ticket_with_peers(args.ticket_peers).await)?;
+        }
+
         if !running.load(Ordering::SeqCst) {
             break; // Exit the loop if ctrl c
         }
@@ -64,8 +235,20 @@ async fn main() -> Result<()>{
     Ok(())
 }
 
-fn write_ticket_to_file(ticket: &str) -> io::Result<()> {
-    let mut file = File::create("./ticket.txt")?; // Create or truncate the file
+// Heights are short, hashes are always 64 hex characters
+async fn find_block(bc_client: &BlockChainClient, block: &str) -> Result<Block> {
+    if let Ok(height) = block.parse::<u128>() && block.len() < 64 {
+        return bc_client.get_block_by_height(height).await?
+            .ok_or_else(|| anyhow::anyhow!("No block at height {}", height));
+    }
+    Ok(bc_client.get_block_from_str(block.to_string()).await?)
+}
+
+fn write_ticket_to_file(path: &Path, ticket: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?; // Nothing else makes the data dir in memory mode
+    }
+    let mut file = File::create(path)?; // Create or truncate the file
     file.write_all(ticket.as_bytes())?; // Write the ticket as bytes
     Ok(())
 }
@@ -1,10 +1,19 @@
-import React, { useEffect, useRef, useContext } from 'react';
+import React, { useEffect, useRef, useContext, useState } from 'react';
 
 import { BlockChainClientWeb, GamePadWeb } from "sm64-crypto-browser";
 import { GameConfig, RngConfig } from "sm64-binds-frontend";
 import { BlockchainContext } from '../context/BlockchainContext';
 import { sm64_record } from "sm64-binds-frontend";
 
+// What session.submit() can return
+const OUTCOME_MESSAGES = {
+    head: "Block accepted, it's the new head",
+    side_chain: "Block valid but behind the head, it was kept on a side chain",
+    stale_parent: "Block rejected, the chain moved on too far while you played",
+    replay_failed: "Block rejected, the inputs didn't win when replayed",
+    too_long: "Block rejected, the run took too long",
+};
+
 // Array<GamePad> -> Array<GamePadWeb>
 function map_solution_to_wasm(solution)  {
     return solution.map(e => new GamePadWeb(e.button, e.stick_x, e.stick_y));
@@ -13,37 +22,33 @@ function map_solution_to_wasm(solution)  {
 function MiningWindow() {  
     const canvasRef = useRef(null);
     const { blockchain } = useContext(BlockchainContext);
+    const [outcome, setOutcome] = useState(null);
 
     async function startMining(canvasRef, blockchain, total_kill_signal = async () => {false}) {
         console.log("---------------------INITIALISED\n\n");
 
-        let cur_head_hash = await blockchain.get_head_hash();
-
         let is_mining = true;
-
-        async function kill_signal() {
-            if (await total_kill_signal()) {
-                is_mining = false;
-                return true;
-            }
-
-            let new_head_hash = await blockchain.get_head_hash();
-            if (cur_head_hash != new_head_hash) {
-                cur_head_hash = new_head_hash;
-                console.log("New block found, restarting game");
-                return true;
-            }
-            return false;
-        }
-
         let max_solution_time = BlockChainClientWeb.get_max_solution_time();
 
         while (is_mining) {
             console.log("------------------ started mine\n\n");
-            let rng_and_seed = await blockchain.start_mine();
-            let seed = rng_and_seed.seed;
+            let session = await blockchain.start_mine();
+            let seed = session.seed;
+
+            async function kill_signal() {
+                if (await total_kill_signal()) {
+                    is_mining = false;
+                    return true;
+                }
+                // A run on a recent parent can still land as a fork block, so only give up once it's too far behind
+                if (session.is_too_deep() || session.is_expired()) {
+                    console.log("Head moved too far or session expired, restarting game");
+                    return true;
+                }
+                return false;
+            }
 
-            let game_config = new GameConfig(max_solution_time, rng_and_seed);
+            let game_config = new GameConfig(max_solution_time, session.rng_and_seed());
 
             let solution;
             try {
@@ -52,7 +57,13 @@ function MiningWindow() {
                 continue;
             }
             solution = map_solution_to_wasm(solution);
-            await blockchain.submit_mine(seed, solution);
+            try {
+                let result = await session.submit(solution);
+                console.log("Submitted block: " + result);
+                setOutcome(OUTCOME_MESSAGES[result] ?? "Block submitted: " + result);
+            } catch (error) {
+                setOutcome("Couldn't submit the block: " + error);
+            }
         }
     }
 
@@ -75,6 +86,7 @@ function MiningWindow() {
 
     return (
         <div id="container">
+            {outcome && <p className="text-light" id="outcome">{outcome}</p>}
             <canvas ref={canvasRef} className="sm64canvas" id="canvas"></canvas>
         </div>
     );